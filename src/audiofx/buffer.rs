@@ -0,0 +1,146 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::Effect;
+
+const I16_SCALE: f32 = 32768.0;
+// 100 ms of stereo audio, enough for every callback teamspeak makes
+const DEFAULT_CAPACITY: usize = 9600;
+
+// the only place where voice samples are converted between i16 and f32,
+// reused across callbacks so the audio thread doesn't allocate
+pub struct AudioBuffer {
+    samples: Vec<f32>,
+    rng: StdRng,
+}
+
+impl Default for AudioBuffer {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl AudioBuffer {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::seeded(capacity, rand::random())
+    }
+
+    // the same seed gives the same dither
+    pub fn seeded(capacity: usize, seed: u64) -> Self {
+        Self {
+            samples: Vec::with_capacity(capacity),
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    // runs the effect on the samples of a voice callback
+    pub fn process(&mut self, effect: &mut dyn Effect, samples: &mut [i16], channels: usize) {
+        self.process_with_gain(effect, 1.0, samples, channels);
+    }
+
+    // like `process`, scaling the result by `gain` before it is converted back
+    pub fn process_with_gain(
+        &mut self,
+        effect: &mut dyn Effect,
+        gain: f32,
+        samples: &mut [i16],
+        channels: usize,
+    ) {
+        self.load(samples);
+        effect.process(&mut self.samples, channels.max(1));
+        if gain != 1.0 {
+            for sample in self.samples.iter_mut() {
+                *sample *= gain;
+            }
+        }
+        self.store(samples);
+    }
+
+    fn load(&mut self, input: &[i16]) {
+        self.samples.clear();
+        self.samples
+            .extend(input.iter().map(|sample| *sample as f32 / I16_SCALE));
+    }
+
+    // clips and adds triangular dither of one lsb before rounding,
+    // digital silence stays silent
+    fn store(&mut self, output: &mut [i16]) {
+        for (sample, value) in output.iter_mut().zip(self.samples.iter()) {
+            if *value == 0.0 {
+                *sample = 0;
+                continue;
+            }
+            let dither = self.rng.gen::<f32>() - self.rng.gen::<f32>();
+            let scaled = (value * I16_SCALE + dither).round();
+            *sample = scaled.clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audiofx::Gain;
+
+    #[test]
+    fn test_round_trip_stays_within_dither() {
+        let mut buffer = AudioBuffer::default();
+        let input: Vec<i16> = (-100..100).map(|i| i * 300).collect();
+        let mut samples = input.clone();
+
+        buffer.process(&mut Gain { gain: 1.0 }, &mut samples, 1);
+
+        for (sample, original) in samples.iter().zip(input) {
+            assert!((*sample as i32 - original as i32).abs() <= 1);
+        }
+    }
+
+    #[test]
+    fn test_clips_instead_of_wrapping() {
+        let mut buffer = AudioBuffer::default();
+        let mut samples = [20000_i16, -20000, 100];
+
+        buffer.process(&mut Gain { gain: 4.0 }, &mut samples, 1);
+
+        assert_eq!(samples[0], i16::MAX);
+        assert_eq!(samples[1], i16::MIN);
+        assert!((399..=401).contains(&samples[2]));
+    }
+
+    #[test]
+    fn test_gain_applies_after_the_effect() {
+        let mut buffer = AudioBuffer::default();
+        let mut samples = [1000_i16, -1000, 0];
+
+        buffer.process_with_gain(&mut Gain { gain: 2.0 }, 1.5, &mut samples, 1);
+
+        assert!((2999..=3001).contains(&samples[0]));
+        assert!((-3001..=-2999).contains(&samples[1]));
+        assert_eq!(samples[2], 0);
+    }
+
+    #[test]
+    fn test_seeded_dither_is_reproducible() {
+        let process = |seed: u64| {
+            let mut samples: Vec<i16> = (0..960).map(|i| i * 30).collect();
+            AudioBuffer::seeded(960, seed).process(&mut Gain { gain: 0.7 }, &mut samples, 1);
+            samples
+        };
+
+        assert_eq!(process(1), process(1));
+        assert_ne!(process(1), process(2));
+    }
+
+    #[test]
+    fn test_buffer_is_reused() {
+        let mut buffer = AudioBuffer::with_capacity(1920);
+        let capacity = buffer.samples.capacity();
+
+        for _ in 0..10 {
+            let mut samples = [1000_i16; 1920];
+            buffer.process(&mut Gain { gain: 0.5 }, &mut samples, 2);
+        }
+
+        assert_eq!(buffer.samples.capacity(), capacity);
+    }
+}
@@ -0,0 +1,228 @@
+use std::f32::consts::FRAC_PI_4;
+
+use super::noise::{NoiseColor, NoiseGenerator};
+use super::Effect;
+use anyhow::Result;
+use iir_filters::filter::{DirectForm2Transposed, Filter as _};
+use iir_filters::filter_design::{butter, FilterType};
+use iir_filters::sos::{zpk2sos, Sos};
+
+const FILTER_ORDER: u32 = 5;
+
+// butterworth filter with one state per channel of an interleaved buffer
+pub struct Filter {
+    sos: Sos,
+    filters: Vec<DirectForm2Transposed>,
+}
+
+impl Filter {
+    pub fn new(filter_type: FilterType, sample_rate: f64) -> Result<Self> {
+        let zpk = butter(FILTER_ORDER, filter_type, sample_rate)?;
+        let sos = zpk2sos(&zpk, None)?;
+
+        Ok(Self {
+            sos,
+            filters: Vec::new(),
+        })
+    }
+}
+
+impl Effect for Filter {
+    fn process(&mut self, samples: &mut [f32], channels: usize) {
+        while self.filters.len() < channels {
+            self.filters.push(DirectForm2Transposed::new(&self.sos));
+        }
+
+        for frame in samples.chunks_mut(channels) {
+            for (sample, filter) in frame.iter_mut().zip(self.filters.iter_mut()) {
+                *sample = filter.filter(*sample as f64) as f32;
+            }
+        }
+    }
+
+    fn fresh(&self, _seed: u64) -> Box<dyn Effect> {
+        Box::new(Self {
+            sos: self.sos.clone(),
+            filters: Vec::new(),
+        })
+    }
+}
+
+pub struct Gain {
+    pub gain: f32,
+}
+
+impl Effect for Gain {
+    fn process(&mut self, samples: &mut [f32], _channels: usize) {
+        for sample in samples.iter_mut() {
+            *sample *= self.gain;
+        }
+    }
+
+    fn fresh(&self, _seed: u64) -> Box<dyn Effect> {
+        Box::new(Self { gain: self.gain })
+    }
+}
+
+// constant power panning between the first two channels, -1.0 is left and 1.0 right
+pub struct Pan {
+    pub pan: f32,
+}
+
+impl Effect for Pan {
+    fn process(&mut self, samples: &mut [f32], channels: usize) {
+        if channels < 2 {
+            return;
+        }
+
+        let angle = (self.pan.clamp(-1.0, 1.0) + 1.0) * FRAC_PI_4;
+        let (left, right) = (angle.cos(), angle.sin());
+        for frame in samples.chunks_mut(channels) {
+            frame[0] *= left;
+            if let Some(sample) = frame.get_mut(1) {
+                *sample *= right;
+            }
+        }
+    }
+
+    fn fresh(&self, _seed: u64) -> Box<dyn Effect> {
+        Box::new(Self { pan: self.pan })
+    }
+}
+
+// noise at a fixed level on top of the signal
+pub struct Noise {
+    pub level: f32,
+    noise: NoiseGenerator,
+}
+
+impl Noise {
+    pub fn new(level: f32, color: NoiseColor, seed: u64) -> Self {
+        Self {
+            level,
+            noise: NoiseGenerator::new(color, seed),
+        }
+    }
+}
+
+impl Effect for Noise {
+    fn process(&mut self, samples: &mut [f32], channels: usize) {
+        for frame in samples.chunks_mut(channels) {
+            let noise = self.noise.next() * self.level;
+            for sample in frame.iter_mut() {
+                *sample += noise;
+            }
+        }
+    }
+
+    fn fresh(&self, seed: u64) -> Box<dyn Effect> {
+        Box::new(Self {
+            level: self.level,
+            noise: NoiseGenerator::new(self.noise.color(), seed),
+        })
+    }
+}
+
+// feedback delay line per channel
+pub struct Delay {
+    length: usize,
+    feedback: f32,
+    wet: f32,
+    lines: Vec<Vec<f32>>,
+    position: usize,
+}
+
+impl Delay {
+    pub fn new(delay_ms: f64, feedback: f32, wet: f32, sample_rate: f64) -> Self {
+        Self {
+            length: ((sample_rate * delay_ms / 1000.0) as usize).max(1),
+            feedback,
+            wet,
+            lines: Vec::new(),
+            position: 0,
+        }
+    }
+}
+
+impl Effect for Delay {
+    fn process(&mut self, samples: &mut [f32], channels: usize) {
+        while self.lines.len() < channels {
+            self.lines.push(vec![0.0; self.length]);
+        }
+
+        for frame in samples.chunks_mut(channels) {
+            for (sample, line) in frame.iter_mut().zip(self.lines.iter_mut()) {
+                let delayed = line[self.position];
+                line[self.position] = *sample + delayed * self.feedback;
+                *sample = *sample * (1.0 - self.wet) + delayed * self.wet;
+            }
+            self.position = (self.position + 1) % self.length;
+        }
+    }
+
+    fn fresh(&self, _seed: u64) -> Box<dyn Effect> {
+        Box::new(Self {
+            length: self.length,
+            feedback: self.feedback,
+            wet: self.wet,
+            lines: Vec::new(),
+            position: 0,
+        })
+    }
+}
+
+// quantizes the signal and adds noise that follows the speaker's volume,
+// which makes the typical crackle of a cheap radio
+pub struct Distortion {
+    pub amount: f32,
+    vol_follow: f32,
+    noise: NoiseGenerator,
+}
+
+impl Distortion {
+    pub fn new(amount: f32, seed: u64) -> Self {
+        Self {
+            amount,
+            vol_follow: 0.0,
+            noise: NoiseGenerator::new(NoiseColor::Crackle, seed),
+        }
+    }
+}
+
+impl Effect for Distortion {
+    fn process(&mut self, samples: &mut [f32], channels: usize) {
+        if samples.is_empty() {
+            return;
+        }
+
+        let mut vol: f32 = samples.iter().map(|sample| sample * sample).sum();
+        vol /= samples.len() as f32;
+
+        // increase for more noise
+        vol *= self.amount;
+
+        // smooth follow from the last frame, both multiplies add up to 1
+        self.vol_follow = self.vol_follow * 0.5 + vol * 0.5;
+
+        let wet = 0.05 * self.amount;
+
+        // every channel of a frame gets the same noise
+        for frame in samples.chunks_mut(channels) {
+            let random = self.noise.next();
+
+            for sample in frame.iter_mut() {
+                let mut temp = *sample + random * self.vol_follow;
+
+                // massively quantized and too loud
+                temp = (temp * 40.0) as i32 as f32 / 40.0;
+                temp = temp * wet + *sample * (1.0 - wet);
+
+                *sample = temp.clamp(-1.0, 1.0);
+            }
+        }
+    }
+
+    fn fresh(&self, seed: u64) -> Box<dyn Effect> {
+        Box::new(Self::new(self.amount, seed))
+    }
+}
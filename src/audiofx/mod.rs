@@ -1,129 +1,144 @@
+mod buffer;
+mod effects;
+mod noise;
+mod presets;
+
 use anyhow::Result;
-use iir_filters::filter::DirectForm2Transposed;
-use iir_filters::filter::Filter;
-use iir_filters::filter_design::butter;
 use iir_filters::filter_design::FilterType;
-use iir_filters::sos::zpk2sos;
-use libdsp_sys::root::DSP::DigitalDelay;
-use rand::prelude::*;
-
-const DELAY_AMOUNT: usize = 50;
-const DELAY_FEEDBACK: f32 = 0.7;
-const DELAY_WET: f32 = 0.7;
-const I16_MAX: f32 = (i16::MAX as f32) + 1.0;
-const I16_MAX_64: f64 = (i16::MAX as f64) + 1.0;
-fn init_delay() -> DigitalDelay {
-    unsafe { DigitalDelay::new(DELAY_AMOUNT, DELAY_FEEDBACK, DELAY_WET) }
-}
 
-pub fn process_delay(input: &mut [i16], delay: &mut DigitalDelay) {
-    unsafe {
-        for sample in input.iter_mut() {
-            *sample = (delay.getNextSample(*sample as f32 / I16_MAX) * I16_MAX) as i16;
-        }
-    }
-}
-
-const CUTOFF_FREQUENCY: f64 = 0.5;
-const SAMPLE_FREQUENCY: f64 = 8000.0;
+pub use buffer::AudioBuffer;
+pub use effects::{Delay, Distortion, Filter, Gain, Noise, Pan};
+pub use noise::NoiseColor;
+pub use presets::{echo, muffle, phone, position, radio, CommunicationType};
 
-pub fn init_lowpass() -> Result<DirectForm2Transposed> {
-    let order = 5;
-    let zpk = butter(
-        order,
-        FilterType::LowPass(CUTOFF_FREQUENCY),
-        SAMPLE_FREQUENCY,
-    )?;
-    let sos = zpk2sos(&zpk, None)?;
+// teamspeak hands every voice callback 48 kHz audio
+pub const SAMPLE_RATE: f64 = 48000.0;
 
-    Ok(DirectForm2Transposed::new(&sos))
+pub trait Effect: Send {
+    // `samples` are interleaved, one frame holds a sample of each of the `channels`
+    fn process(&mut self, samples: &mut [f32], channels: usize);
+    // an unused copy with the given noise seed, cheaper than building it from its config
+    // again because the filters are already designed
+    fn fresh(&self, seed: u64) -> Box<dyn Effect>;
 }
 
-pub fn process_lowpass(input: &mut [i16], dft2: &mut DirectForm2Transposed) {
-    for sample in input.iter_mut() {
-        *sample = (dft2.filter(*sample as f64 / I16_MAX_64) * I16_MAX_64) as i16;
-    }
+// a single effect of a preset, frequencies in Hz
+#[derive(Clone, Debug, PartialEq)]
+pub enum EffectConfig {
+    LowPass {
+        cutoff: f64,
+    },
+    HighPass {
+        cutoff: f64,
+    },
+    BandPass {
+        low: f64,
+        high: f64,
+    },
+    Gain {
+        gain: f32,
+    },
+    // -1.0 is left and 1.0 right
+    Pan {
+        pan: f32,
+    },
+    Noise {
+        level: f32,
+        color: NoiseColor,
+    },
+    Delay {
+        delay_ms: f64,
+        feedback: f32,
+        wet: f32,
+    },
+    Distortion {
+        amount: f32,
+    },
 }
 
-const BAND_PASS_LOW: f64 = 50.0;
-const BAND_PASS_HIGH: f64 = 2600.0;
-
-pub fn init_band_pass() -> Result<DirectForm2Transposed> {
-    let order = 5;
-    let zpk = butter(
-        order,
-        FilterType::BandPass(BAND_PASS_LOW, BAND_PASS_HIGH),
-        SAMPLE_FREQUENCY,
-    )?;
-    let sos = zpk2sos(&zpk, None)?;
-
-    Ok(DirectForm2Transposed::new(&sos))
+impl EffectConfig {
+    // `seed` feeds the noise of the effect
+    pub fn build(&self, sample_rate: f64, seed: u64) -> Result<Box<dyn Effect>> {
+        Ok(match *self {
+            EffectConfig::LowPass { cutoff } => {
+                Box::new(Filter::new(FilterType::LowPass(cutoff), sample_rate)?)
+            }
+            EffectConfig::HighPass { cutoff } => {
+                Box::new(Filter::new(FilterType::HighPass(cutoff), sample_rate)?)
+            }
+            EffectConfig::BandPass { low, high } => {
+                Box::new(Filter::new(FilterType::BandPass(low, high), sample_rate)?)
+            }
+            EffectConfig::Gain { gain } => Box::new(Gain { gain }),
+            EffectConfig::Pan { pan } => Box::new(Pan { pan }),
+            EffectConfig::Noise { level, color } => Box::new(Noise::new(level, color, seed)),
+            EffectConfig::Delay {
+                delay_ms,
+                feedback,
+                wet,
+            } => Box::new(Delay::new(delay_ms, feedback, wet, sample_rate)),
+            EffectConfig::Distortion { amount } => Box::new(Distortion::new(amount, seed)),
+        })
+    }
 }
 
-const HIGH_PASS_FREQ: f64 = 2000.0;
-
-pub fn init_high_pass() -> Result<DirectForm2Transposed> {
-    let order = 5;
-    let zpk = butter(
-        order,
-        FilterType::HighPass(HIGH_PASS_FREQ),
-        SAMPLE_FREQUENCY,
-    )?;
-    let sos = zpk2sos(&zpk, None)?;
-
-    Ok(DirectForm2Transposed::new(&sos))
+// runs its effects one after another
+#[derive(Default)]
+pub struct EffectChain {
+    effects: Vec<Box<dyn Effect>>,
 }
 
-const FUDGE: f32 = 10.0;
-
-pub fn process_radio(input: &mut [i16], vol_follow: &mut f32) {
-    let mut samples: Vec<f32> = input
-        .iter()
-        .map(|x| *x as f32 / I16_MAX)
-        .collect::<Vec<_>>();
-
-    let frame_count = input.len();
-    let mut vol: f32 = 0.0;
-    for i in 0..frame_count {
-        vol += samples[i] * samples[i];
+impl EffectChain {
+    pub fn from_config(config: &[EffectConfig], sample_rate: f64) -> Result<Self> {
+        Self::from_config_seeded(config, sample_rate, rand::random())
     }
-    vol /= frame_count as f32;
-
-    // Fudge factor, increase for more noise
-    vol *= FUDGE;
 
-    // Smooth follow from last frame, both multiplies add up to 1...
-    *vol_follow = *vol_follow * 0.5 + vol * 0.5;
+    // the same seed gives the same output, every effect gets its own noise
+    pub fn from_config_seeded(
+        config: &[EffectConfig],
+        sample_rate: f64,
+        seed: u64,
+    ) -> Result<Self> {
+        Ok(Self {
+            effects: config
+                .iter()
+                .enumerate()
+                .map(|(index, effect)| effect.build(sample_rate, seed.wrapping_add(index as u64)))
+                .collect::<Result<_>>()?,
+        })
+    }
 
-    // Between -1.0 and 1.0...
-    let mut random = (rand::thread_rng().gen::<u16>() % 32768) as f32 / 16384.0 - 1.0;
+    pub fn preset(communication: CommunicationType, sample_rate: f64) -> Result<Self> {
+        Self::from_config(&communication.preset(), sample_rate)
+    }
 
-    // Between 1 and 128...
-    let mut count = (rand::thread_rng().gen::<u8>() % 128) + 1;
-    let mut temp: f32;
-    for i in 0..frame_count {
-        if count == 0 {
-            // Between -1.0 and 1.0...
-            random = (rand::thread_rng().gen::<u16>() % 32768) as f32 / 16384.0 - 1.0;
-            // Between 1 and 128...
-            count = (rand::thread_rng().gen::<u8>() % 128) + 1;
+    // seeds its effects like `from_config_seeded` does
+    pub fn fresh_chain(&self, seed: u64) -> Self {
+        Self {
+            effects: self
+                .effects
+                .iter()
+                .enumerate()
+                .map(|(index, effect)| effect.fresh(seed.wrapping_add(index as u64)))
+                .collect(),
         }
-        // Add random to inputs multiplied by current volume;
-        temp = samples[i] + random * *vol_follow;
-
-        // Make it an integer between -60 and 60
-        temp = (temp * 40.0) as i32 as f32;
+    }
 
-        // Drop it back down but massively quantized and too high
-        temp = temp / 40.0;
-        temp *= 0.05 * FUDGE;
-        temp += samples[i] * (1.0 - (0.05 * FUDGE));
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+}
 
-        samples[i] = temp.clamp(-1.0, 1.0);
+impl Effect for EffectChain {
+    fn process(&mut self, samples: &mut [f32], channels: usize) {
+        let channels = channels.max(1);
+        for effect in self.effects.iter_mut() {
+            effect.process(samples, channels);
+        }
     }
 
-    for (it, sample) in samples.iter().enumerate() {
-        input[it] = (*sample * I16_MAX) as i16;
+    fn fresh(&self, seed: u64) -> Box<dyn Effect> {
+        Box::new(self.fresh_chain(seed))
     }
 }
 
@@ -131,25 +146,109 @@ pub fn process_radio(input: &mut [i16], vol_follow: &mut f32) {
 mod tests {
     use super::*;
 
+    fn chain(config: &[EffectConfig]) -> EffectChain {
+        EffectChain::from_config(config, SAMPLE_RATE).unwrap()
+    }
+
     #[test]
     fn test_echo_filter() {
-        let mut delay = init_delay();
+        let mut delay = chain(&[EffectConfig::Delay {
+            delay_ms: 25.0,
+            feedback: 0.7,
+            wet: 0.7,
+        }]);
 
-        let mut input = [2000_i16; 100];
+        let mut input = [2000_i16; 2400];
 
-        process_delay(&mut input, &mut delay);
+        AudioBuffer::default().process(&mut delay, &mut input, 1);
 
-        assert_ne!(input, [2000_i16; 100]);
+        assert_ne!(input, [2000_i16; 2400]);
     }
 
     #[test]
     fn test_lowpass_filter() {
-        let mut lowpass = init_lowpass().unwrap();
+        let mut lowpass = chain(&[EffectConfig::LowPass { cutoff: 3000.0 }]);
 
         let mut input = [2000_i16; 100];
 
-        process_lowpass(&mut input, &mut lowpass);
+        AudioBuffer::default().process(&mut lowpass, &mut input, 1);
 
         assert_ne!(input, [2000_i16; 100]);
     }
+
+    #[test]
+    fn test_filters_keep_channels_apart() {
+        let mut lowpass = chain(&[EffectConfig::LowPass { cutoff: 3000.0 }]);
+
+        // loud left channel, silent right channel
+        let mut input: Vec<i16> = (0..200)
+            .map(|i| if i % 2 == 0 { 2000 } else { 0 })
+            .collect();
+
+        AudioBuffer::default().process(&mut lowpass, &mut input, 2);
+
+        assert!(input.iter().skip(1).step_by(2).all(|sample| *sample == 0));
+        assert!(input.iter().step_by(2).any(|sample| *sample != 0));
+    }
+
+    #[test]
+    fn test_chain_runs_effects_in_order() {
+        let mut effects = chain(&[
+            EffectConfig::Gain { gain: 0.5 },
+            EffectConfig::Pan { pan: 1.0 },
+        ]);
+
+        let mut samples = [0.5, 0.5, -0.5, -0.5];
+        effects.process(&mut samples, 2);
+
+        for (sample, expected) in samples.iter().zip([0.0, 0.25, 0.0, -0.25]) {
+            assert!((sample - expected).abs() < 1e-6, "{:?}", samples);
+        }
+    }
+
+    #[test]
+    fn test_seeded_chains_are_reproducible() {
+        let process = |seed: u64| {
+            let config = CommunicationType::Radio.preset();
+            let mut effects = EffectChain::from_config_seeded(&config, SAMPLE_RATE, seed).unwrap();
+            let mut samples: Vec<f32> = (0..960).map(|i| (i as f32 * 0.05).sin() * 0.5).collect();
+            effects.process(&mut samples, 1);
+            samples
+        };
+
+        assert_eq!(process(1), process(1));
+        assert_ne!(process(1), process(2));
+    }
+
+    #[test]
+    fn test_fresh_chain_starts_over() {
+        let config = CommunicationType::Phone.preset();
+        let process = |effects: &mut EffectChain| {
+            let mut samples: Vec<f32> = (0..960).map(|i| (i as f32 * 0.05).sin() * 0.5).collect();
+            effects.process(&mut samples, 1);
+            samples
+        };
+
+        let mut used = EffectChain::from_config_seeded(&config, SAMPLE_RATE, 1).unwrap();
+        process(&mut used);
+        let mut expected = EffectChain::from_config_seeded(&config, SAMPLE_RATE, 7).unwrap();
+
+        assert_eq!(process(&mut used.fresh_chain(7)), process(&mut expected));
+    }
+
+    #[test]
+    fn test_presets_build() {
+        for communication in CommunicationType::ALL {
+            let mut effects = EffectChain::preset(communication, SAMPLE_RATE).unwrap();
+            let mut samples = [0.25_f32; 960];
+            effects.process(&mut samples, 2);
+
+            assert!(samples.iter().all(|sample| sample.is_finite()));
+        }
+        assert!(
+            EffectChain::preset(CommunicationType::Proximity, SAMPLE_RATE)
+                .unwrap()
+                .is_empty()
+        );
+    }
 }
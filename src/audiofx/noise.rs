@@ -0,0 +1,96 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoiseColor {
+    White,
+    // -3 dB per octave, sounds like a bad line
+    Pink,
+    // random values held for a random number of samples, the static of a radio
+    Crackle,
+}
+
+// seedable noise between -1.0 and 1.0, the same seed always gives the same noise
+pub struct NoiseGenerator {
+    color: NoiseColor,
+    rng: StdRng,
+    // pink filter state
+    pink: [f32; 3],
+    // crackle value and how many samples it is held for
+    held: f32,
+    remaining: u32,
+}
+
+impl NoiseGenerator {
+    pub fn new(color: NoiseColor, seed: u64) -> Self {
+        Self {
+            color,
+            rng: StdRng::seed_from_u64(seed),
+            pink: [0.0; 3],
+            held: 0.0,
+            remaining: 0,
+        }
+    }
+
+    pub fn color(&self) -> NoiseColor {
+        self.color
+    }
+
+    pub fn next(&mut self) -> f32 {
+        match self.color {
+            NoiseColor::White => self.white(),
+            NoiseColor::Pink => {
+                // paul kellet's economy filter
+                let white = self.white();
+                let [b0, b1, b2] = &mut self.pink;
+                *b0 = 0.99765 * *b0 + white * 0.0990460;
+                *b1 = 0.96300 * *b1 + white * 0.2965164;
+                *b2 = 0.57000 * *b2 + white * 1.0526913;
+                ((*b0 + *b1 + *b2 + white * 0.1848) * 0.25).clamp(-1.0, 1.0)
+            }
+            NoiseColor::Crackle => {
+                if self.remaining == 0 {
+                    self.held = self.white();
+                    // between 1 and 128
+                    self.remaining = self.rng.gen_range(1..=128);
+                }
+                self.remaining -= 1;
+                self.held
+            }
+        }
+    }
+
+    fn white(&mut self) -> f32 {
+        self.rng.gen_range(-1.0..1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate(color: NoiseColor, seed: u64) -> Vec<f32> {
+        let mut noise = NoiseGenerator::new(color, seed);
+        (0..4800).map(|_| noise.next()).collect()
+    }
+
+    #[test]
+    fn test_seed_makes_noise_reproducible() {
+        for color in [NoiseColor::White, NoiseColor::Pink, NoiseColor::Crackle] {
+            let noise = generate(color, 42);
+
+            assert_eq!(noise, generate(color, 42));
+            assert_ne!(noise, generate(color, 43));
+            assert!(noise.iter().all(|value| (-1.0..=1.0).contains(value)));
+        }
+    }
+
+    #[test]
+    fn test_crackle_holds_values() {
+        let noise = generate(NoiseColor::Crackle, 7);
+        let changes = noise.windows(2).filter(|pair| pair[0] != pair[1]).count();
+
+        // values are held for 64 samples on average
+        assert!(changes > 0 && changes < noise.len() / 16, "{}", changes);
+    }
+}
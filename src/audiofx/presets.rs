@@ -0,0 +1,154 @@
+use super::{EffectConfig, NoiseColor};
+
+const RADIO_BAND_LOW: f64 = 50.0;
+const RADIO_BAND_HIGH: f64 = 2600.0;
+const PHONE_BAND_LOW: f64 = 300.0;
+const PHONE_BAND_HIGH: f64 = 3400.0;
+
+// how a voice reaches the listener, every type has its own effect preset
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CommunicationType {
+    Proximity,
+    Radio,
+    Phone,
+    Megaphone,
+}
+
+impl CommunicationType {
+    pub const ALL: [CommunicationType; 4] = [
+        CommunicationType::Proximity,
+        CommunicationType::Radio,
+        CommunicationType::Phone,
+        CommunicationType::Megaphone,
+    ];
+
+    pub fn preset(self) -> Vec<EffectConfig> {
+        match self {
+            CommunicationType::Proximity => Vec::new(),
+            CommunicationType::Radio => radio(1.0),
+            CommunicationType::Phone => phone(1.0),
+            CommunicationType::Megaphone => vec![
+                EffectConfig::HighPass { cutoff: 400.0 },
+                EffectConfig::LowPass { cutoff: 5000.0 },
+                EffectConfig::Distortion { amount: 4.0 },
+                // slap back from the buildings around
+                EffectConfig::Delay {
+                    delay_ms: 80.0,
+                    feedback: 0.2,
+                    wet: 0.25,
+                },
+                EffectConfig::Gain { gain: 1.5 },
+            ],
+        }
+    }
+}
+
+// `quality` between 0.0 and 1.0, a bad signal crackles and hisses more
+pub fn radio(quality: f32) -> Vec<EffectConfig> {
+    let loss = 1.0 - quality.clamp(0.0, 1.0);
+    vec![
+        EffectConfig::BandPass {
+            low: RADIO_BAND_LOW,
+            high: RADIO_BAND_HIGH,
+        },
+        EffectConfig::Distortion {
+            amount: 10.0 + loss * 10.0,
+        },
+        // hiss of an open channel
+        EffectConfig::Noise {
+            level: 0.001 + loss * 0.02,
+            color: NoiseColor::White,
+        },
+    ]
+}
+
+// `signal` strength between 0.0 and 1.0
+pub fn phone(signal: f32) -> Vec<EffectConfig> {
+    let loss = 1.0 - signal.clamp(0.0, 1.0);
+    let mut effects = vec![
+        EffectConfig::BandPass {
+            low: PHONE_BAND_LOW,
+            high: PHONE_BAND_HIGH,
+        },
+        EffectConfig::Noise {
+            level: 0.002 + loss * 0.01,
+            color: NoiseColor::Pink,
+        },
+    ];
+    if loss > 0.0 {
+        effects.push(EffectConfig::Noise {
+            level: loss * 0.05,
+            color: NoiseColor::Crackle,
+        });
+    }
+    effects.push(EffectConfig::Gain { gain: 1.2 });
+    effects
+}
+
+// a voice behind walls, same intensity as the muffle of the game
+pub fn muffle(intensity: i32) -> Vec<EffectConfig> {
+    let cutoff = 12000.0 / (1.0 + intensity.max(0) as f64);
+    vec![
+        EffectConfig::LowPass {
+            cutoff: cutoff.max(200.0),
+        },
+        EffectConfig::Gain { gain: 0.8 },
+    ]
+}
+
+// `rolloff` is how much of the echo comes back every time
+pub fn echo(delay_ms: f64, rolloff: f32) -> Vec<EffectConfig> {
+    vec![EffectConfig::Delay {
+        delay_ms,
+        feedback: rolloff.clamp(0.0, 0.95),
+        wet: 0.5,
+    }]
+}
+
+// a speaker `x` meters to the right and `y` meters in front of the listener,
+// the voice fades out towards the edge of its `range`
+pub fn position(x: f32, y: f32, range: f32) -> Vec<EffectConfig> {
+    let distance = (x * x + y * y).sqrt();
+    let pan = if distance > 0.0 { x / distance } else { 0.0 };
+    let gain = if range > 0.0 {
+        (1.0 - distance / range).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    vec![EffectConfig::Pan { pan }, EffectConfig::Gain { gain }]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_position() {
+        assert_eq!(
+            position(3.0, 4.0, 10.0),
+            vec![
+                EffectConfig::Pan { pan: 0.6 },
+                EffectConfig::Gain { gain: 0.5 }
+            ]
+        );
+        assert_eq!(
+            position(0.0, 0.0, 10.0),
+            vec![
+                EffectConfig::Pan { pan: 0.0 },
+                EffectConfig::Gain { gain: 1.0 }
+            ]
+        );
+        assert_eq!(
+            position(-20.0, 0.0, 10.0)[1],
+            EffectConfig::Gain { gain: 0.0 }
+        );
+    }
+
+    #[test]
+    fn test_full_signal_is_the_default_preset() {
+        assert_eq!(radio(1.0), CommunicationType::Radio.preset());
+        assert_eq!(phone(1.0), CommunicationType::Phone.preset());
+        assert_eq!(phone(0.5).len(), phone(1.0).len() + 1);
+    }
+}
@@ -0,0 +1,165 @@
+use std::env;
+
+use anyhow::{anyhow, bail, Context, Result};
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use rustychat::audiofx::{self, AudioBuffer, CommunicationType, Effect, EffectChain, EffectConfig};
+
+const USAGE: &str = "usage: fxtool <input.wav> <output.wav> <effect> [parameters]
+
+effects:
+    radio [quality 0.0-1.0]
+    phone [signal 0.0-1.0]
+    megaphone
+    muffle [intensity]
+    echo [delay ms] [rolloff]
+    position <x meters right> <y meters ahead> [voice range]";
+
+// teamspeak hands the plugin 20 ms of audio per callback
+const CALLBACKS_PER_SECOND: u32 = 50;
+// the same seed gives the same noise and dither on every run
+const SEED: u64 = 0;
+
+fn main() {
+    if let Err(error) = run(&env::args().skip(1).collect::<Vec<_>>()) {
+        eprintln!("{:#}", error);
+        std::process::exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<()> {
+    let (input, output, effect, params) = match args {
+        [input, output, effect, params @ ..] => (input, output, effect, params),
+        _ => bail!(USAGE),
+    };
+    let config = parse_effect(effect, params)?;
+
+    let mut reader = WavReader::open(input).with_context(|| format!("failed to open {}", input))?;
+    let mut spec = reader.spec();
+    let mut effects = EffectChain::from_config_seeded(&config, spec.sample_rate as f64, SEED)?;
+
+    // panning needs a left and a right channel
+    let upmix = effect == "position" && spec.channels == 1;
+    if upmix {
+        spec.channels = 2;
+    }
+    let channels = spec.channels as usize;
+    let chunk = (spec.sample_rate / CALLBACKS_PER_SECOND).max(1) as usize * channels;
+
+    match (spec.sample_format, spec.bits_per_sample) {
+        (SampleFormat::Int, 16) => {
+            let mut samples: Vec<i16> = reader.samples().collect::<Result<_, _>>()?;
+            if upmix {
+                samples = to_stereo(&samples);
+            }
+
+            let mut buffer = AudioBuffer::seeded(chunk, SEED);
+            for samples in samples.chunks_mut(chunk) {
+                buffer.process(&mut effects, samples, channels);
+            }
+            write(output, spec, &samples)
+        }
+        (SampleFormat::Float, 32) => {
+            let mut samples: Vec<f32> = reader.samples().collect::<Result<_, _>>()?;
+            if upmix {
+                samples = to_stereo(&samples);
+            }
+
+            for samples in samples.chunks_mut(chunk) {
+                effects.process(samples, channels);
+            }
+            write(output, spec, &samples)
+        }
+        (format, bits) => bail!("unsupported wav format {:?} with {} bits", format, bits),
+    }
+}
+
+fn parse_effect(name: &str, params: &[String]) -> Result<Vec<EffectConfig>> {
+    let param = |index: usize, default: Option<f32>| -> Result<f32> {
+        match params.get(index) {
+            Some(value) => value
+                .parse()
+                .with_context(|| format!("invalid parameter {}", value)),
+            None => default.ok_or_else(|| anyhow!(USAGE)),
+        }
+    };
+
+    // defaults are the ones of the game protocol
+    Ok(match name {
+        "radio" => audiofx::radio(param(0, Some(1.0))?),
+        "phone" => audiofx::phone(param(0, Some(1.0))?),
+        "megaphone" => CommunicationType::Megaphone.preset(),
+        "muffle" => audiofx::muffle(param(0, Some(10.0))? as i32),
+        "echo" => audiofx::echo(param(0, Some(25.0))? as f64, param(1, Some(0.3))?),
+        "position" => audiofx::position(param(0, None)?, param(1, None)?, param(2, Some(8.0))?),
+        _ => bail!(USAGE),
+    })
+}
+
+fn to_stereo<T: Copy>(samples: &[T]) -> Vec<T> {
+    samples
+        .iter()
+        .flat_map(|sample| [*sample, *sample])
+        .collect()
+}
+
+fn write<S: hound::Sample + Copy>(path: &str, spec: WavSpec, samples: &[S]) -> Result<()> {
+    let mut writer =
+        WavWriter::create(path, spec).with_context(|| format!("failed to create {}", path))?;
+    for sample in samples {
+        writer.write_sample(*sample)?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(params: &[&str]) -> Vec<String> {
+        params.iter().map(|param| param.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_effect() {
+        assert_eq!(
+            parse_effect("radio", &params(&[])).unwrap(),
+            CommunicationType::Radio.preset()
+        );
+        assert_eq!(
+            parse_effect("echo", &params(&["100", "0.5"])).unwrap(),
+            audiofx::echo(100.0, 0.5)
+        );
+
+        assert!(parse_effect("position", &params(&["1.0"])).is_err());
+        assert!(parse_effect("radio", &params(&["loud"])).is_err());
+        assert!(parse_effect("flanger", &params(&[])).is_err());
+    }
+
+    #[test]
+    fn test_output_is_reproducible() {
+        let dir = env::temp_dir().join(format!("fxtool-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_owned();
+
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let samples: Vec<i16> = (0..48000)
+            .map(|i| ((i as f32 * 0.05).sin() * 8000.0) as i16)
+            .collect();
+        write(&path("input.wav"), spec, &samples).unwrap();
+
+        for output in ["first.wav", "second.wav"] {
+            run(&params(&[&path("input.wav"), &path(output), "radio"])).unwrap();
+        }
+        let first = std::fs::read(path("first.wav")).unwrap();
+        let second = std::fs::read(path("second.wav")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(first, second);
+    }
+}
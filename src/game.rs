@@ -1,91 +1,520 @@
+mod clients;
+mod radio;
+mod targets;
+
 use std::{
-    ffi::{c_char, CString},
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use ts3plugin::{ChannelId, ConnectionId, LogLevel, ServerId, Visibility};
+
+use crate::audiofx::CommunicationType;
+use crate::teamspeak::{self, SoundState, TeamSpeakBackend};
+use crate::websocket;
+use crate::websocket::protocol::{
+    Error, GameInstanceState, InitiateParameter, PlayerStateDelta, PlayerStateUpdateParameter,
+    SelfStateUpdateParameter,
 };
+use clients::ClientIndex;
+use radio::{RadioChannel, RadioState};
+use targets::{TargetManager, TargetReason};
 
-use ts3plugin::{ChannelId, ConnectionId, ServerId, TsApi, Visibility, TsApiLock};
+const MAX_NICKNAME_LENGTH: usize = 30;
 
-use crate::websocket::protocol::InitiateParameter;
+const REJOIN_POLL_INTERVAL: Duration = Duration::from_millis(250);
+const REJOIN_BASE_DELAY: Duration = Duration::from_millis(500);
+const REJOIN_MAX_DELAY: Duration = Duration::from_secs(16);
+const REJOIN_MAX_ATTEMPTS: u32 = 6;
 
+// one game instance per connected server tab, keyed by the ts3 server connection handler id
+#[derive(Default)]
 pub struct GameHandler {
-    pub server_id: Option<u64>,
-    pub own_client_id: Option<u16>,
-    pub original_channel: Option<u16>,
-    pub game_channel: Option<u16>,
+    pub sessions: HashMap<u64, GameSession>,
+}
+
+pub struct GameSession {
+    pub server_id: u64,
+    pub server_uid: String,
+    // name the game knows the local player by
+    pub game_name: String,
+    // nickname actually applied, the game name with a fallback suffix if it was taken
+    pub nickname: String,
+    pub own_client_id: u16,
+    pub original_channel: Option<u64>,
+    pub game_channel: u64,
+    pub swiss_channels: Vec<u64>,
     pub in_game: bool,
+    pub in_game_channel: bool,
+    pub in_swiss_channel: bool,
+    pub rejoin_at: Option<Instant>,
+    pub rejoin_attempts: u32,
+    // last sound state reported to the game
+    pub sound_state: Option<SoundState>,
+    pub send_talk_states: bool,
+    pub radio: RadioState,
+    pub targets: TargetManager,
+    pub clients: ClientIndex,
+    // clients our voice is currently whispered to, empty when talking to the channel
+    pub whisper_targets: Vec<ConnectionId>,
+    // the last whisper list wasn't applied, our voice is held back until one is
+    pub whisper_list_failed: bool,
+    // latest states the game sent, the other players keyed by game name
+    pub self_state: Option<SelfStateUpdateParameter>,
+    pub players: HashMap<String, PlayerStateUpdateParameter>,
+}
+
+// retries moving the own client back into the game channel after it got moved out
+pub fn start_rejoin_watcher(game_ref: Arc<Mutex<GameHandler>>) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(REJOIN_POLL_INTERVAL);
+
+        // the game handler lock is released before locking the ts api
+        let rejoins = game_ref.lock().unwrap().poll_rejoins(Instant::now());
+        if rejoins.is_empty() {
+            continue;
+        }
+
+        teamspeak::with_ts_api(|ts| {
+            for (server_id, client_id, channel_id) in rejoins {
+                println!(
+                    "[SERVER-{}] rejoining game channel {}",
+                    server_id, channel_id
+                );
+                let _ = ts.move_client(
+                    ServerId(server_id),
+                    ConnectionId(client_id),
+                    ChannelId(channel_id),
+                );
+            }
+        });
+    });
 }
 
 impl GameHandler {
-    pub fn initiate(&mut self, params: InitiateParameter) {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn initiate(&mut self, ts: &dyn TeamSpeakBackend, params: InitiateParameter) {
         println!(
             "initiate rustychat for server {}",
             params.server_unique_identifier
         );
 
-        let ts_api = TsApi::lock_api().unwrap();
-        let servers = ts_api.get_server_ids();
-
-        for server in servers {
-            if *ts_api.get_server(server).unwrap().get_uid().unwrap()
-                == params.server_unique_identifier
-            {
-                self.server_id = Some(server.0);
-            }
-        }
-
-        let server = match self.server_id {
-            Some(id) => ts_api
-                .get_server(ServerId(id))
-                .unwrap(),
+        let server_id = match ts.server_by_uid(&params.server_unique_identifier) {
+            Some(server_id) => server_id,
             None => {
                 println!("no server found matching the uid of the client request");
+                let _ = websocket::on_error(
+                    &params.server_unique_identifier,
+                    Error::NotConnectedToServer,
+                    "no server found matching the uid",
+                );
                 return;
             }
         };
 
-        if *server.get_uid().unwrap() != *params.server_unique_identifier {
-            println!("this is the wrong server.");
+        let own_client = match ts.own_client_id(server_id) {
+            Ok(own_client) => own_client,
+            Err(err) => {
+                let _ = websocket::on_error(
+                    &params.server_unique_identifier,
+                    Error::NotConnectedToServer,
+                    &err.to_string(),
+                );
+                return;
+            }
+        };
+
+        if !ts.channel_exists(server_id, ChannelId(params.channel_id)) {
+            let _ = websocket::on_error(
+                &params.server_unique_identifier,
+                Error::ChannelNotAvailable,
+                &format!("channel {} does not exist", params.channel_id),
+            );
             return;
         }
 
-        let own_client = server.get_own_connection_id().unwrap();
+        let mut session = GameSession {
+            server_id: server_id.0,
+            server_uid: params.server_unique_identifier,
+            game_name: params.name.to_owned(),
+            nickname: params.name.to_owned(),
+            own_client_id: own_client.0,
+            original_channel: ts
+                .client_channel(server_id, own_client)
+                .ok()
+                .map(|channel_id| channel_id.0),
+            game_channel: params.channel_id,
+            swiss_channels: params.swiss_channel_ids,
+            in_game: false,
+            in_game_channel: false,
+            in_swiss_channel: false,
+            rejoin_at: None,
+            rejoin_attempts: 0,
+            sound_state: None,
+            send_talk_states: params.send_talk_states,
+            radio: RadioState::default(),
+            targets: TargetManager::default(),
+            clients: ClientIndex::default(),
+            whisper_targets: Vec::new(),
+            whisper_list_failed: false,
+            self_state: None,
+            players: HashMap::new(),
+        };
+
+        // a repeated initiate replaces the session, whoever it whispered to must not keep
+        // hearing us
+        if let Some(mut previous) = self.sessions.remove(&server_id.0) {
+            previous.set_whisper_list(ts, Vec::new());
+        }
+
+        match session.ts_rename_client(ts, &params.name, params.name_fallback_attempts) {
+            Ok(nickname) if nickname != params.name => {
+                let _ = websocket::on_error(&session.server_uid, Error::NameFallback, &nickname);
+                session.nickname = nickname;
+            }
+            Ok(_) => {}
+            Err(err) => {
+                println!("failed to rename client: {}", err);
+                let _ = websocket::on_error(
+                    &session.server_uid,
+                    Error::NameNotAvailable,
+                    &err.to_string(),
+                );
+                if let Err(err) = ts.set_whisper_list(server_id, &[]) {
+                    println!("failed to clear whisper list: {}", err);
+                }
+                return;
+            }
+        }
 
-        self.own_client_id = Some(own_client.0);
+        session.clients = ClientIndex::from_backend(ts, server_id);
+        session.in_game = true;
+        if session.original_channel == Some(params.channel_id) {
+            session.on_channel_switched(ts, own_client, ChannelId(params.channel_id));
+        } else if let Err(err) = session.ts_join_channel(ts, params.channel_id) {
+            println!("failed to join game channel: {}", err);
+        }
 
-        let _server_id = server.get_id().0;
+        session.report_sound_state(ts);
+        self.sessions.insert(session.server_id, session);
+    }
 
-        self.ts_rename_client(&ts_api, params.name);
-        self.ts_join_channel(&ts_api, params.channel_id);
+    pub fn ts_on_talking_changed(
+        &self,
+        ts: &dyn TeamSpeakBackend,
+        server_id: ServerId,
+        connection_id: ConnectionId,
+        is_talking: bool,
+    ) {
+        if let Some(session) = self.sessions.get(&server_id.0) {
+            session.report_talk_state(ts, connection_id, is_talking);
+        }
+    }
 
+    // the game reports radio traffic we take part in, our own under our game name
+    pub fn radio_communication_update(
+        &mut self,
+        ts: &dyn TeamSpeakBackend,
+        server_uid: &str,
+        name: &str,
+        secondary: bool,
+    ) {
+        if let Some(session) = self.session_by_uid_mut(server_uid) {
+            if session.is_own_name(name) {
+                session
+                    .radio
+                    .start_transmission(RadioChannel::from_secondary(secondary));
+                session.update_radio_targets(ts);
+            } else {
+                session.radio.start_receiving(name);
+            }
+        }
     }
 
-    pub fn ws_connected(&mut self) {
-        TsApi::lock_api().unwrap().log_or_print(
-            "WS Connected!",
-            "RustyChat",
-            ts3plugin::LogLevel::Info,
-        )
+    pub fn stop_radio_communication(
+        &mut self,
+        ts: &dyn TeamSpeakBackend,
+        server_uid: &str,
+        name: &str,
+    ) {
+        if let Some(session) = self.session_by_uid_mut(server_uid) {
+            if session.is_own_name(name) {
+                session.radio.stop_transmission();
+                session.update_radio_targets(ts);
+            } else {
+                session.radio.stop_receiving(name);
+            }
+        }
     }
 
-    // this functions should be integrated into rust ts3 plugin via PR
-    pub fn ts_rename_client(&mut self, ts_api: &TsApiLock, nick: String) {
-        unsafe {
-            let raw_api: &ts3plugin::Ts3Functions = ts_api.get_raw_api();
-            let server_id = self.server_id.unwrap();
-            let name: *const c_char = CString::new(nick).unwrap().into_raw();
-            (raw_api.set_client_self_variable_as_string)(server_id, 1, name);
+    pub fn add_radio_channel_member(
+        &mut self,
+        ts: &dyn TeamSpeakBackend,
+        server_uid: &str,
+        name: &str,
+        is_primary_channel: bool,
+    ) {
+        if let Some(session) = self.session_by_uid_mut(server_uid) {
+            session
+                .radio
+                .add_member(RadioChannel::from_primary(is_primary_channel), name);
+            session.update_radio_targets(ts);
         }
     }
 
-    pub fn ts_join_channel(&mut self, ts_api: &TsApiLock, channel_id: u64) {
-        unsafe {
-            let raw_api: &ts3plugin::Ts3Functions = ts_api.get_raw_api();
-            let c: i8 = 0;
-            (raw_api.request_client_move)(self.server_id.unwrap(), self.own_client_id.unwrap(), channel_id, &c, &c);
+    pub fn update_radio_channel_members(
+        &mut self,
+        ts: &dyn TeamSpeakBackend,
+        server_uid: &str,
+        names: Vec<String>,
+        is_primary_channel: bool,
+    ) {
+        if let Some(session) = self.session_by_uid_mut(server_uid) {
+            session
+                .radio
+                .set_members(RadioChannel::from_primary(is_primary_channel), names);
+            session.update_radio_targets(ts);
+        }
+    }
+
+    pub fn remove_radio_channel_member(
+        &mut self,
+        ts: &dyn TeamSpeakBackend,
+        server_uid: &str,
+        name: &str,
+        is_primary_channel: bool,
+    ) {
+        if let Some(session) = self.session_by_uid_mut(server_uid) {
+            session
+                .radio
+                .remove_member(RadioChannel::from_primary(is_primary_channel), name);
+            session.update_radio_targets(ts);
+        }
+    }
+
+    pub fn self_state_update(
+        &mut self,
+        ts: &dyn TeamSpeakBackend,
+        server_uid: &str,
+        self_state: SelfStateUpdateParameter,
+    ) {
+        if let Some(session) = self.session_by_uid_mut(server_uid) {
+            session.self_state = Some(self_state);
+            session.update_proximity(ts);
+        }
+    }
+
+    pub fn player_state_update(
+        &mut self,
+        ts: &dyn TeamSpeakBackend,
+        server_uid: &str,
+        player: PlayerStateUpdateParameter,
+    ) {
+        if let Some(session) = self.session_by_uid_mut(server_uid) {
+            session.players.insert(player.name.to_owned(), player);
+            session.update_proximity(ts);
+        }
+    }
+
+    // deltas for players without a full state yet are ignored
+    pub fn player_state_deltas(
+        &mut self,
+        ts: &dyn TeamSpeakBackend,
+        server_uid: &str,
+        deltas: Vec<PlayerStateDelta>,
+    ) {
+        if let Some(session) = self.session_by_uid_mut(server_uid) {
+            for delta in deltas {
+                if let Some(player) = session.players.get_mut(&delta.name) {
+                    apply_player_delta(player, delta);
+                }
+            }
+            session.update_proximity(ts);
+        }
+    }
+
+    // replaces the state of every player
+    pub fn bulk_update(
+        &mut self,
+        ts: &dyn TeamSpeakBackend,
+        server_uid: &str,
+        self_state: SelfStateUpdateParameter,
+        players: Vec<PlayerStateUpdateParameter>,
+    ) {
+        if let Some(session) = self.session_by_uid_mut(server_uid) {
+            session.self_state = Some(self_state);
+            session.players = players
+                .into_iter()
+                .map(|player| (player.name.to_owned(), player))
+                .collect();
+            session.update_proximity(ts);
+        }
+    }
+
+    pub fn remove_player(&mut self, ts: &dyn TeamSpeakBackend, server_uid: &str, name: &str) {
+        if let Some(session) = self.session_by_uid_mut(server_uid) {
+            session.players.remove(name);
+            session.update_proximity(ts);
+        }
+    }
+
+    // the state the game last sent for a client we hear, found through its nickname
+    pub fn player_state(
+        &self,
+        server_id: ServerId,
+        connection_id: ConnectionId,
+    ) -> Option<&PlayerStateUpdateParameter> {
+        let session = self.sessions.get(&server_id.0)?;
+        session.players.get(session.clients.name(connection_id)?)
+    }
+
+    // the game sends the other party of our call, the call lasts until it is stopped
+    pub fn phone_communication_update(
+        &mut self,
+        ts: &dyn TeamSpeakBackend,
+        server_uid: &str,
+        name: &str,
+    ) {
+        if let Some(session) = self.session_by_uid_mut(server_uid) {
+            if session.targets.add(TargetReason::Phone, name) {
+                session.update_whisper_list(ts);
+            }
+        }
+    }
+
+    pub fn stop_phone_communication(
+        &mut self,
+        ts: &dyn TeamSpeakBackend,
+        server_uid: &str,
+        name: &str,
+    ) {
+        if let Some(session) = self.session_by_uid_mut(server_uid) {
+            if session.targets.remove(TargetReason::Phone, name) {
+                session.update_whisper_list(ts);
+            }
         }
     }
 
+    // in the game channel our voice is only sent while the whisper list holds somebody,
+    // anywhere else everyone in the channel listens, nobody does while the whisper list
+    // couldn't be set
+    pub fn has_listeners(&self, server_id: ServerId) -> bool {
+        self.sessions
+            .get(&server_id.0)
+            .map(|session| {
+                !session.whisper_list_failed
+                    && (!session.in_game
+                        || !session.in_game_channel
+                        || !session.whisper_targets.is_empty())
+            })
+            .unwrap_or(true)
+    }
+
+    // the radio effect is only applied to our own voice while transmitting
+    pub fn is_transmitting_radio(&self, server_id: ServerId) -> bool {
+        self.sessions
+            .get(&server_id.0)
+            .map(|session| session.in_game && session.radio.transmitting().is_some())
+            .unwrap_or(false)
+    }
+
+    // how the voice of another client reaches us, picks the effects applied to it
+    pub fn incoming_communication(
+        &self,
+        server_id: ServerId,
+        connection_id: ConnectionId,
+    ) -> CommunicationType {
+        self.sessions
+            .get(&server_id.0)
+            .filter(|session| session.in_game)
+            .and_then(|session| {
+                let name = session.clients.name(connection_id)?;
+                if session.radio.is_receiving(name) {
+                    Some(CommunicationType::Radio)
+                } else if session.targets.contains(TargetReason::Phone, name) {
+                    Some(CommunicationType::Phone)
+                } else {
+                    None
+                }
+            })
+            .unwrap_or(CommunicationType::Proximity)
+    }
+
+    fn session_by_uid_mut(&mut self, server_uid: &str) -> Option<&mut GameSession> {
+        self.sessions
+            .values_mut()
+            .find(|session| session.server_uid == server_uid)
+    }
+
+    // a client connected, entered our view or changed its nickname
+    pub fn ts_on_client_updated(
+        &mut self,
+        ts: &dyn TeamSpeakBackend,
+        server_id: ServerId,
+        connection_id: ConnectionId,
+    ) {
+        if let Some(session) = self.sessions.get_mut(&server_id.0) {
+            match ts.client_name(server_id, connection_id) {
+                Ok(name) => session.clients.insert(connection_id, &name),
+                Err(err) => println!("failed to get name of client: {}", err),
+            }
+            session.update_whisper_list(ts);
+        }
+    }
+
+    pub fn ts_on_client_left(
+        &mut self,
+        ts: &dyn TeamSpeakBackend,
+        server_id: ServerId,
+        connection_id: ConnectionId,
+    ) {
+        if let Some(session) = self.sessions.get_mut(&server_id.0) {
+            session.clients.remove(connection_id);
+            session.update_whisper_list(ts);
+        }
+    }
+
+    pub fn ts_on_sound_state_changed(&mut self, ts: &dyn TeamSpeakBackend, server_id: ServerId) {
+        if let Some(session) = self.sessions.get_mut(&server_id.0) {
+            session.report_sound_state(ts);
+        }
+    }
+
+    pub fn active_instances(&self) -> u32 {
+        self.sessions
+            .values()
+            .filter(|session| session.in_game)
+            .count() as u32
+    }
+
+    // voice processing is suspended while an admin holds us in a swiss channel
+    pub fn is_voice_suspended(&self, server_id: ServerId) -> bool {
+        self.sessions
+            .get(&server_id.0)
+            .map(|session| session.in_game && session.in_swiss_channel)
+            .unwrap_or(false)
+    }
+
+    pub fn ws_connected(&mut self, ts: &dyn TeamSpeakBackend) {
+        ts.log("WS Connected!", LogLevel::Info)
+    }
+
+    // returns the moves to request for every session with a due rejoin
+    pub fn poll_rejoins(&mut self, now: Instant) -> Vec<(u64, u16, u64)> {
+        self.sessions
+            .values_mut()
+            .filter_map(|session| session.poll_rejoin(now))
+            .collect()
+    }
+
     pub fn ts_on_channel_switched(
         &mut self,
+        ts: &dyn TeamSpeakBackend,
         server_id: ServerId,
         connection_id: ConnectionId,
         channel_id: ChannelId,
@@ -95,5 +524,869 @@ impl GameHandler {
             "[SERVER-{}] con {} moved to channel {}",
             server_id.0, connection_id.0, channel_id.0
         );
+
+        if let Some(session) = self.sessions.get_mut(&server_id.0) {
+            session.on_channel_switched(ts, connection_id, channel_id);
+        }
+    }
+
+    // ends the game session and moves the own client back where it came from
+    pub fn reset(&mut self, ts: &dyn TeamSpeakBackend, server_uid: &str) {
+        let server_id = match self
+            .sessions
+            .values()
+            .find(|session| session.server_uid == server_uid)
+        {
+            Some(session) => session.server_id,
+            None => return,
+        };
+
+        let mut session = self.sessions.remove(&server_id).unwrap();
+        println!("[SERVER-{}] game session reset", server_id);
+
+        session.set_whisper_list(ts, Vec::new());
+
+        if let Some(original_channel) = session.original_channel {
+            let in_game_channel = ts
+                .client_channel(ServerId(server_id), ConnectionId(session.own_client_id))
+                .map(|channel_id| channel_id.0 == session.game_channel)
+                .unwrap_or(false);
+
+            if in_game_channel && original_channel != session.game_channel {
+                if let Err(err) = session.ts_join_channel(ts, original_channel) {
+                    println!("failed to move back to original channel: {}", err);
+                }
+            }
+        }
+    }
+
+    pub fn ts_on_disconnected(&mut self, server_id: ServerId) {
+        if let Some(session) = self.sessions.remove(&server_id.0) {
+            println!("[SERVER-{}] game session ended", server_id.0);
+            let _ = websocket::on_instance_state_change(
+                &session.server_uid,
+                GameInstanceState::NotConnected,
+            );
+        }
+    }
+}
+
+impl GameSession {
+    // the game may refer to us by the name it asked for or by the nickname we reported
+    fn is_own_name(&self, name: &str) -> bool {
+        self.game_name == name || self.nickname == name
+    }
+
+    // renames the own client, falling back to "<nick> (2)", "<nick> (3)", ...
+    // for up to `fallback_attempts` tries when the nickname is taken
+    pub fn ts_rename_client(
+        &self,
+        ts: &dyn TeamSpeakBackend,
+        nick: &str,
+        fallback_attempts: u8,
+    ) -> Result<String> {
+        let server_id = ServerId(self.server_id);
+        let own_client_id = ConnectionId(self.own_client_id);
+
+        for attempt in 0..=fallback_attempts {
+            let candidate = fallback_nickname(nick, attempt);
+
+            if ts.client_name(server_id, own_client_id).ok().as_ref() == Some(&candidate) {
+                return Ok(candidate);
+            }
+
+            if ts.is_nickname_in_use(server_id, &candidate)? {
+                println!("nickname {} is already in use", candidate);
+                continue;
+            }
+
+            match ts.rename_self(server_id, &candidate) {
+                Ok(()) => {
+                    if attempt > 0 {
+                        ts.log(
+                            &format!("nickname {} taken, using {}", nick, candidate),
+                            LogLevel::Warning,
+                        );
+                    }
+                    return Ok(candidate);
+                }
+                Err(err) => println!("renaming to {} failed: {}", candidate, err),
+            }
+        }
+
+        Err(anyhow!("nickname {} is not available", nick))
+    }
+
+    pub fn ts_join_channel(&self, ts: &dyn TeamSpeakBackend, channel_id: u64) -> Result<()> {
+        ts.move_client(
+            ServerId(self.server_id),
+            ConnectionId(self.own_client_id),
+            ChannelId(channel_id),
+        )
+    }
+
+    // returns the move to request when a scheduled rejoin is due
+    pub fn poll_rejoin(&mut self, now: Instant) -> Option<(u64, u16, u64)> {
+        let rejoin_at = self.rejoin_at?;
+        if !self.in_game || now < rejoin_at {
+            return None;
+        }
+
+        if self.rejoin_attempts >= REJOIN_MAX_ATTEMPTS {
+            self.rejoin_at = None;
+            let _ = websocket::on_error(
+                &self.server_uid,
+                Error::ChannelNotAvailable,
+                "failed to rejoin the game channel",
+            );
+            return None;
+        }
+
+        self.rejoin_attempts += 1;
+        self.rejoin_at = Some(now + rejoin_delay(self.rejoin_attempts));
+
+        Some((self.server_id, self.own_client_id, self.game_channel))
+    }
+
+    // only players in the game channel are reported, the own client by its game name
+    fn report_talk_state(
+        &self,
+        ts: &dyn TeamSpeakBackend,
+        connection_id: ConnectionId,
+        is_talking: bool,
+    ) {
+        if !self.in_game || !self.send_talk_states {
+            return;
+        }
+
+        let name = if connection_id.0 == self.own_client_id {
+            if !self.in_game_channel {
+                return;
+            }
+            &self.game_name
+        } else {
+            let in_game_channel = ts
+                .client_channel(ServerId(self.server_id), connection_id)
+                .map(|channel_id| channel_id.0 == self.game_channel)
+                .unwrap_or(false);
+            if !in_game_channel {
+                return;
+            }
+
+            match self.clients.name(connection_id) {
+                Some(name) => name,
+                None => {
+                    println!("talking client {} is not indexed", connection_id.0);
+                    return;
+                }
+            }
+        };
+
+        let _ = websocket::on_talk_state_toggle(&self.server_uid, is_talking, name);
+    }
+
+    // while transmitting on the radio the members of that channel are targeted as well
+    // players within our voice range hear us without any radio or phone, the whisper
+    // list is only touched when that set changed
+    fn update_proximity(&mut self, ts: &dyn TeamSpeakBackend) {
+        let self_state = match &self.self_state {
+            Some(self_state) => self_state,
+            None => return,
+        };
+
+        let in_range = targets::players_in_range(self_state, self.players.values());
+        if self.targets.set(TargetReason::Proximity, &in_range) {
+            self.update_whisper_list(ts);
+        }
+    }
+
+    fn update_radio_targets(&mut self, ts: &dyn TeamSpeakBackend) {
+        let listeners = self.radio.listeners().cloned().unwrap_or_default();
+        if self.targets.set(TargetReason::Radio, &listeners) {
+            self.update_whisper_list(ts);
+        }
+    }
+
+    // whispering only happens in the game channel, in a swiss channel or after being
+    // moved out we talk to the channel we are in
+    fn update_whisper_list(&mut self, ts: &dyn TeamSpeakBackend) {
+        if !self.in_game_channel {
+            return self.set_whisper_list(ts, Vec::new());
+        }
+
+        let mut targets: Vec<ConnectionId> = self
+            .targets
+            .names()
+            .filter_map(|name| self.clients.connection_id(name))
+            .filter(|client_id| client_id.0 != self.own_client_id)
+            .collect();
+        targets.sort_by_key(|client_id| client_id.0);
+        self.set_whisper_list(ts, targets);
+    }
+
+    fn set_whisper_list(&mut self, ts: &dyn TeamSpeakBackend, targets: Vec<ConnectionId>) {
+        if targets == self.whisper_targets && !self.whisper_list_failed {
+            return;
+        }
+
+        match ts.set_whisper_list(ServerId(self.server_id), &targets) {
+            Ok(()) => {
+                self.whisper_targets = targets;
+                self.whisper_list_failed = false;
+            }
+            Err(err) => {
+                println!("failed to set whisper list: {}", err);
+                self.whisper_list_failed = true;
+            }
+        }
+    }
+
+    // sends the mute and hardware state unless the game already has it
+    fn report_sound_state(&mut self, ts: &dyn TeamSpeakBackend) {
+        let sound_state = match ts.sound_state(ServerId(self.server_id)) {
+            Ok(sound_state) => sound_state,
+            Err(err) => {
+                println!("failed to read sound state: {}", err);
+                return;
+            }
+        };
+        if self.sound_state == Some(sound_state) {
+            return;
+        }
+
+        self.sound_state = Some(sound_state);
+        let _ = websocket::on_sound_state_toggle(&self.server_uid, sound_state);
+    }
+
+    pub fn on_channel_switched(
+        &mut self,
+        ts: &dyn TeamSpeakBackend,
+        connection_id: ConnectionId,
+        channel_id: ChannelId,
+    ) {
+        if !self.in_game || self.own_client_id != connection_id.0 {
+            return;
+        }
+
+        let state = if self.swiss_channels.contains(&channel_id.0) {
+            if self.in_swiss_channel {
+                return;
+            }
+            self.in_swiss_channel = true;
+            self.in_game_channel = false;
+            self.rejoin_at = None;
+            GameInstanceState::InSwissChannel
+        } else if self.game_channel == channel_id.0 {
+            if self.in_game_channel {
+                return;
+            }
+            self.in_game_channel = true;
+            self.in_swiss_channel = false;
+            self.rejoin_at = None;
+            self.rejoin_attempts = 0;
+            GameInstanceState::Ingame
+        } else {
+            // moved or kicked out of the game channel, try to get back in
+            if self.rejoin_at.is_some() {
+                return;
+            }
+            self.in_game_channel = false;
+            self.in_swiss_channel = false;
+            self.rejoin_at = Some(Instant::now() + REJOIN_BASE_DELAY);
+            self.rejoin_attempts = 0;
+            GameInstanceState::Connected
+        };
+        self.update_whisper_list(ts);
+
+        if let Err(err) = websocket::on_instance_state_change(&self.server_uid, state) {
+            println!("failed to report instance state: {}", err);
+        }
+    }
+}
+
+fn apply_player_delta(player: &mut PlayerStateUpdateParameter, delta: PlayerStateDelta) {
+    if let Some(position) = delta.position {
+        player.position = position;
+    }
+    if let Some(rotation) = delta.rotation {
+        player.rotation = rotation;
+    }
+    if let Some(voice_range) = delta.voice_range {
+        player.voice_range = voice_range;
+    }
+    if let Some(is_alive) = delta.is_alive {
+        player.is_alive = is_alive;
+    }
+    if let Some(distance_culled) = delta.distance_culled {
+        player.distance_culled = distance_culled;
+    }
+}
+
+fn rejoin_delay(attempt: u32) -> Duration {
+    REJOIN_BASE_DELAY
+        .saturating_mul(1 << attempt.min(16))
+        .min(REJOIN_MAX_DELAY)
+}
+
+fn fallback_nickname(nick: &str, attempt: u8) -> String {
+    if attempt == 0 {
+        return nick.to_owned();
+    }
+
+    let suffix = format!(" ({})", attempt as u32 + 1);
+    let base: String = nick
+        .chars()
+        .take(MAX_NICKNAME_LENGTH.saturating_sub(suffix.chars().count()))
+        .collect();
+
+    format!("{}{}", base, suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::teamspeak::mock::MockTeamSpeak;
+
+    const LOBBY: ChannelId = ChannelId(1);
+    const GAME_CHANNEL: ChannelId = ChannelId(10);
+    const SUPPORT_CHANNEL: ChannelId = ChannelId(20);
+
+    fn initiate_parameter(uid: &str, name: &str, name_fallback_attempts: u8) -> InitiateParameter {
+        serde_json::from_value(serde_json::json!({
+            "ServerUniqueIdentifier": uid,
+            "Name": name,
+            "ChannelId": GAME_CHANNEL.0,
+            "ChannelPassword": "",
+            "SoundPack": "default",
+            "SwissChannelIds": [SUPPORT_CHANNEL.0],
+            "NameFallbackAttempts": name_fallback_attempts,
+        }))
+        .unwrap()
+    }
+
+    fn connect(ts: &MockTeamSpeak, uid: &str) -> ServerId {
+        let server_id = ts.connect(uid, "TS Nickname", LOBBY);
+        ts.add_channel(server_id, GAME_CHANNEL, "Game");
+        ts.add_channel(server_id, SUPPORT_CHANNEL, "Support");
+        server_id
+    }
+
+    // delivers the moves requested through the backend like connection_move would
+    fn apply_moves(handler: &mut GameHandler, ts: &MockTeamSpeak) {
+        for mock_move in ts.take_moves() {
+            handler.ts_on_channel_switched(
+                ts,
+                mock_move.server_id,
+                mock_move.client_id,
+                mock_move.channel_id,
+                Visibility::Retain,
+            );
+        }
+    }
+
+    fn move_own_client(
+        handler: &mut GameHandler,
+        ts: &MockTeamSpeak,
+        server_id: ServerId,
+        channel_id: ChannelId,
+    ) {
+        let own_client_id = ts.own_client_id(server_id).unwrap();
+        ts.force_move(server_id, own_client_id, channel_id);
+        handler.ts_on_channel_switched(
+            ts,
+            server_id,
+            own_client_id,
+            channel_id,
+            Visibility::Retain,
+        );
+    }
+
+    #[test]
+    fn test_initiate_renames_and_joins_game_channel() {
+        let ts = MockTeamSpeak::new();
+        let server_id = connect(&ts, "server-a");
+        let mut handler = GameHandler::new();
+
+        handler.initiate(&ts, initiate_parameter("server-a", "John Doe", 0));
+        apply_moves(&mut handler, &ts);
+
+        let own_client_id = ts.own_client_id(server_id).unwrap();
+        assert_eq!(
+            ts.client_name(server_id, own_client_id).unwrap(),
+            "John Doe"
+        );
+        assert_eq!(
+            ts.client_channel(server_id, own_client_id).unwrap(),
+            GAME_CHANNEL
+        );
+        assert_eq!(handler.active_instances(), 1);
+        assert!(!handler.is_voice_suspended(server_id));
+    }
+
+    #[test]
+    fn test_initiate_unknown_server_or_channel() {
+        let ts = MockTeamSpeak::new();
+        let server_id = ts.connect("server-a", "TS Nickname", LOBBY);
+        let mut handler = GameHandler::new();
+
+        handler.initiate(&ts, initiate_parameter("server-b", "John Doe", 0));
+        handler.initiate(&ts, initiate_parameter("server-a", "John Doe", 0));
+
+        assert_eq!(handler.active_instances(), 0);
+        assert!(ts.take_moves().is_empty());
+        let own_client_id = ts.own_client_id(server_id).unwrap();
+        assert_eq!(
+            ts.client_name(server_id, own_client_id).unwrap(),
+            "TS Nickname"
+        );
+    }
+
+    #[test]
+    fn test_initiate_name_collision() {
+        let ts = MockTeamSpeak::new();
+        let server_id = connect(&ts, "server-a");
+        ts.add_client(server_id, "John Doe", GAME_CHANNEL);
+        let mut handler = GameHandler::new();
+
+        handler.initiate(&ts, initiate_parameter("server-a", "John Doe", 0));
+        assert_eq!(handler.active_instances(), 0);
+
+        handler.initiate(&ts, initiate_parameter("server-a", "John Doe", 2));
+        let own_client_id = ts.own_client_id(server_id).unwrap();
+        assert_eq!(handler.active_instances(), 1);
+        assert_eq!(
+            ts.client_name(server_id, own_client_id).unwrap(),
+            "John Doe (2)"
+        );
+        assert!(ts.logs().iter().any(|log| log.contains("John Doe (2)")));
+
+        let session = &handler.sessions[&server_id.0];
+        assert_eq!(session.game_name, "John Doe");
+        assert_eq!(session.nickname, "John Doe (2)");
+    }
+
+    #[test]
+    fn test_radio_under_fallback_nickname() {
+        let ts = MockTeamSpeak::new();
+        let server_id = connect(&ts, "server-a");
+        ts.add_client(server_id, "John Doe", LOBBY);
+        let jane = ts.add_client(server_id, "Jane Doe", GAME_CHANNEL);
+        let mut handler = GameHandler::new();
+
+        handler.initiate(&ts, initiate_parameter("server-a", "John Doe", 1));
+        apply_moves(&mut handler, &ts);
+        handler.add_radio_channel_member(&ts, "server-a", "Jane Doe", true);
+
+        handler.radio_communication_update(&ts, "server-a", "John Doe (2)", false);
+        assert_eq!(ts.whisper_list(server_id), vec![jane]);
+
+        handler.stop_radio_communication(&ts, "server-a", "John Doe (2)");
+        assert!(ts.whisper_list(server_id).is_empty());
+    }
+
+    #[test]
+    fn test_voice_follows_applied_whisper_list() {
+        let ts = MockTeamSpeak::new();
+        let server_id = connect(&ts, "server-a");
+        let jane = ts.add_client(server_id, "Jane Doe", GAME_CHANNEL);
+        let mut handler = GameHandler::new();
+
+        handler.initiate(&ts, initiate_parameter("server-a", "John Doe", 0));
+        apply_moves(&mut handler, &ts);
+        assert!(!handler.has_listeners(server_id));
+
+        // a target without a ts client can't be whispered to
+        handler.phone_communication_update(&ts, "server-a", "Max Mustermann");
+        assert!(ts.whisper_list(server_id).is_empty());
+        assert!(!handler.has_listeners(server_id));
+
+        ts.set_whisper_list_failing(server_id, true);
+        handler.phone_communication_update(&ts, "server-a", "Jane Doe");
+        assert!(!handler.has_listeners(server_id));
+
+        // the failed list is applied again with the next change
+        ts.set_whisper_list_failing(server_id, false);
+        handler.stop_phone_communication(&ts, "server-a", "Max Mustermann");
+        assert_eq!(ts.whisper_list(server_id), vec![jane]);
+        assert!(handler.has_listeners(server_id));
+    }
+
+    #[test]
+    fn test_initiate_again_clears_whisper_list() {
+        let ts = MockTeamSpeak::new();
+        let server_id = connect(&ts, "server-a");
+        let jane = ts.add_client(server_id, "Jane Doe", GAME_CHANNEL);
+        ts.add_client(server_id, "Max Mustermann", LOBBY);
+        let mut handler = GameHandler::new();
+
+        handler.initiate(&ts, initiate_parameter("server-a", "John Doe", 0));
+        apply_moves(&mut handler, &ts);
+        handler.phone_communication_update(&ts, "server-a", "Jane Doe");
+        assert_eq!(ts.whisper_list(server_id), vec![jane]);
+
+        handler.initiate(&ts, initiate_parameter("server-a", "John Doe", 0));
+        assert!(ts.whisper_list(server_id).is_empty());
+
+        apply_moves(&mut handler, &ts);
+        handler.phone_communication_update(&ts, "server-a", "Jane Doe");
+        assert_eq!(ts.whisper_list(server_id), vec![jane]);
+
+        // the name is taken, the session ends without anyone left to whisper to
+        handler.initiate(&ts, initiate_parameter("server-a", "Max Mustermann", 0));
+        assert!(ts.whisper_list(server_id).is_empty());
+        assert_eq!(handler.active_instances(), 0);
+    }
+
+    #[test]
+    fn test_swiss_channel_suspends_voice() {
+        let ts = MockTeamSpeak::new();
+        let server_id = connect(&ts, "server-a");
+        let mut handler = GameHandler::new();
+
+        handler.initiate(&ts, initiate_parameter("server-a", "John Doe", 0));
+        apply_moves(&mut handler, &ts);
+
+        move_own_client(&mut handler, &ts, server_id, SUPPORT_CHANNEL);
+        assert!(handler.is_voice_suspended(server_id));
+        assert!(handler
+            .poll_rejoins(Instant::now() + REJOIN_MAX_DELAY)
+            .is_empty());
+
+        move_own_client(&mut handler, &ts, server_id, GAME_CHANNEL);
+        assert!(!handler.is_voice_suspended(server_id));
+    }
+
+    #[test]
+    fn test_whisper_list_only_in_game_channel() {
+        let ts = MockTeamSpeak::new();
+        let server_id = connect(&ts, "server-a");
+        let jane = ts.add_client(server_id, "Jane Doe", GAME_CHANNEL);
+        let mut handler = GameHandler::new();
+
+        handler.initiate(&ts, initiate_parameter("server-a", "John Doe", 0));
+        handler.add_radio_channel_member(&ts, "server-a", "Jane Doe", true);
+        handler.radio_communication_update(&ts, "server-a", "John Doe", false);
+        assert!(ts.whisper_list(server_id).is_empty());
+
+        apply_moves(&mut handler, &ts);
+        assert_eq!(ts.whisper_list(server_id), vec![jane]);
+
+        // an admin talks to us in the support channel without the game's targets
+        move_own_client(&mut handler, &ts, server_id, SUPPORT_CHANNEL);
+        assert!(ts.whisper_list(server_id).is_empty());
+        assert!(handler.has_listeners(server_id));
+
+        move_own_client(&mut handler, &ts, server_id, GAME_CHANNEL);
+        assert_eq!(ts.whisper_list(server_id), vec![jane]);
+
+        move_own_client(&mut handler, &ts, server_id, LOBBY);
+        assert!(ts.whisper_list(server_id).is_empty());
+
+        // targets changing while we are out are picked up on the way back in
+        handler.stop_radio_communication(&ts, "server-a", "John Doe");
+        handler.phone_communication_update(&ts, "server-a", "Jane Doe");
+        assert!(ts.whisper_list(server_id).is_empty());
+
+        move_own_client(&mut handler, &ts, server_id, GAME_CHANNEL);
+        assert_eq!(ts.whisper_list(server_id), vec![jane]);
+    }
+
+    #[test]
+    fn test_rejoin_after_being_moved_out() {
+        let ts = MockTeamSpeak::new();
+        let server_id = connect(&ts, "server-a");
+        let mut handler = GameHandler::new();
+
+        handler.initiate(&ts, initiate_parameter("server-a", "John Doe", 0));
+        apply_moves(&mut handler, &ts);
+
+        move_own_client(&mut handler, &ts, server_id, LOBBY);
+        assert!(handler.poll_rejoins(Instant::now()).is_empty());
+
+        let rejoins = handler.poll_rejoins(Instant::now() + REJOIN_BASE_DELAY);
+        let own_client_id = ts.own_client_id(server_id).unwrap();
+        assert_eq!(
+            rejoins,
+            vec![(server_id.0, own_client_id.0, GAME_CHANNEL.0)]
+        );
+
+        for (server_id, client_id, channel_id) in rejoins {
+            ts.move_client(
+                ServerId(server_id),
+                ConnectionId(client_id),
+                ChannelId(channel_id),
+            )
+            .unwrap();
+        }
+        apply_moves(&mut handler, &ts);
+
+        assert!(handler
+            .poll_rejoins(Instant::now() + REJOIN_MAX_DELAY)
+            .is_empty());
+        assert_eq!(
+            ts.client_channel(server_id, own_client_id).unwrap(),
+            GAME_CHANNEL
+        );
+    }
+
+    #[test]
+    fn test_reset_returns_to_original_channel() {
+        let ts = MockTeamSpeak::new();
+        let server_id = connect(&ts, "server-a");
+        let mut handler = GameHandler::new();
+
+        handler.initiate(&ts, initiate_parameter("server-a", "John Doe", 0));
+        apply_moves(&mut handler, &ts);
+
+        handler.reset(&ts, "server-a");
+        apply_moves(&mut handler, &ts);
+
+        let own_client_id = ts.own_client_id(server_id).unwrap();
+        assert_eq!(handler.active_instances(), 0);
+        assert_eq!(ts.client_channel(server_id, own_client_id).unwrap(), LOBBY);
+    }
+
+    #[test]
+    fn test_radio_transmission_whispers_to_members() {
+        let ts = MockTeamSpeak::new();
+        let server_id = connect(&ts, "server-a");
+        let jane = ts.add_client(server_id, "Jane Doe", GAME_CHANNEL);
+        let max = ts.add_client(server_id, "Max Mustermann", GAME_CHANNEL);
+        ts.add_client(server_id, "Erika Mustermann", GAME_CHANNEL);
+        let mut handler = GameHandler::new();
+
+        handler.initiate(&ts, initiate_parameter("server-a", "John Doe", 0));
+        apply_moves(&mut handler, &ts);
+        handler.update_radio_channel_members(
+            &ts,
+            "server-a",
+            vec!["John Doe".to_owned(), "Jane Doe".to_owned()],
+            true,
+        );
+        handler.add_radio_channel_member(&ts, "server-a", "Max Mustermann", false);
+
+        // other players' radio traffic doesn't make us transmit
+        handler.radio_communication_update(&ts, "server-a", "Jane Doe", false);
+        assert!(!handler.is_transmitting_radio(server_id));
+        assert!(ts.whisper_list(server_id).is_empty());
+
+        handler.radio_communication_update(&ts, "server-a", "John Doe", false);
+        assert!(handler.is_transmitting_radio(server_id));
+        assert_eq!(ts.whisper_list(server_id), vec![jane]);
+
+        handler.add_radio_channel_member(&ts, "server-a", "Max Mustermann", true);
+        assert_eq!(ts.whisper_list(server_id), vec![jane, max]);
+
+        handler.radio_communication_update(&ts, "server-a", "John Doe", true);
+        assert_eq!(ts.whisper_list(server_id), vec![max]);
+
+        handler.stop_radio_communication(&ts, "server-a", "John Doe");
+        assert!(!handler.is_transmitting_radio(server_id));
+        assert!(ts.whisper_list(server_id).is_empty());
+    }
+
+    #[test]
+    fn test_incoming_communication_per_speaker() {
+        let ts = MockTeamSpeak::new();
+        let server_id = connect(&ts, "server-a");
+        let jane = ts.add_client(server_id, "Jane Doe", GAME_CHANNEL);
+        let max = ts.add_client(server_id, "Max Mustermann", GAME_CHANNEL);
+        let erika = ts.add_client(server_id, "Erika Mustermann", GAME_CHANNEL);
+        let mut handler = GameHandler::new();
+
+        handler.initiate(&ts, initiate_parameter("server-a", "John Doe", 0));
+        apply_moves(&mut handler, &ts);
+        handler.radio_communication_update(&ts, "server-a", "Jane Doe", false);
+        handler.phone_communication_update(&ts, "server-a", "Max Mustermann");
+
+        assert_eq!(
+            handler.incoming_communication(server_id, jane),
+            CommunicationType::Radio
+        );
+        assert_eq!(
+            handler.incoming_communication(server_id, max),
+            CommunicationType::Phone
+        );
+        assert_eq!(
+            handler.incoming_communication(server_id, erika),
+            CommunicationType::Proximity
+        );
+
+        handler.stop_radio_communication(&ts, "server-a", "Jane Doe");
+        handler.stop_phone_communication(&ts, "server-a", "Max Mustermann");
+        assert_eq!(
+            handler.incoming_communication(server_id, jane),
+            CommunicationType::Proximity
+        );
+        assert_eq!(
+            handler.incoming_communication(server_id, max),
+            CommunicationType::Proximity
+        );
+    }
+
+    fn player(name: &str, position: [f32; 3]) -> PlayerStateUpdateParameter {
+        serde_json::from_value(serde_json::json!({
+            "Name": name,
+            "Position": { "X": position[0], "Y": position[1], "Z": position[2] },
+            "Rotation": 0.0,
+            "VoiceRange": 8.0,
+            "IsAlive": true,
+            "VolumeOverride": null,
+            "DistanceCulled": false,
+            "Muffle": null,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_player_state_deltas() {
+        let ts = MockTeamSpeak::new();
+        let server_id = connect(&ts, "server-a");
+        let jane = ts.add_client(server_id, "Jane Doe", GAME_CHANNEL);
+        let max = ts.add_client(server_id, "Max Mustermann", GAME_CHANNEL);
+        let mut handler = GameHandler::new();
+
+        handler.initiate(&ts, initiate_parameter("server-a", "John Doe", 0));
+        for name in ["Jane Doe", "Max Mustermann"] {
+            handler.player_state_update(&ts, "server-a", player(name, [0.0, 0.0, 0.0]));
+        }
+        let deltas: Vec<PlayerStateDelta> = serde_json::from_value(serde_json::json!([
+            { "Name": "Jane Doe", "Position": { "X": 1.0, "Y": 2.0, "Z": 3.0 } },
+            { "Name": "Max Mustermann", "VoiceRange": 15.0 },
+            { "Name": "Unknown", "VoiceRange": 15.0 },
+        ]))
+        .unwrap();
+        handler.player_state_deltas(&ts, "server-a", deltas);
+
+        assert_eq!(handler.sessions[&server_id.0].players.len(), 2);
+        let jane = handler.player_state(server_id, jane).unwrap();
+        assert_eq!(
+            (jane.position.x, jane.position.y, jane.position.z),
+            (1.0, 2.0, 3.0)
+        );
+        assert_eq!(jane.voice_range, 8.0);
+        let max = handler.player_state(server_id, max).unwrap();
+        assert_eq!(max.position.x, 0.0);
+        assert_eq!(max.voice_range, 15.0);
+    }
+
+    #[test]
+    fn test_proximity_follows_player_states() {
+        let ts = MockTeamSpeak::new();
+        let server_id = connect(&ts, "server-a");
+        let jane = ts.add_client(server_id, "Jane Doe", GAME_CHANNEL);
+        let mut handler = GameHandler::new();
+
+        handler.initiate(&ts, initiate_parameter("server-a", "John Doe", 0));
+        apply_moves(&mut handler, &ts);
+        let self_state = serde_json::from_value(serde_json::json!({
+            "Position": { "X": 0.0, "Y": 0.0, "Z": 0.0 },
+            "Rotation": 0.0,
+            "VoiceRange": 8.0,
+        }))
+        .unwrap();
+        handler.bulk_update(
+            &ts,
+            "server-a",
+            self_state,
+            vec![player("Jane Doe", [1.0, 0.0, 0.0])],
+        );
+        assert_eq!(ts.whisper_list(server_id), vec![jane]);
+
+        handler.player_state_update(&ts, "server-a", player("Jane Doe", [100.0, 0.0, 0.0]));
+        assert!(ts.whisper_list(server_id).is_empty());
+
+        handler.player_state_update(&ts, "server-a", player("Jane Doe", [1.0, 0.0, 0.0]));
+        handler.remove_player(&ts, "server-a", "Jane Doe");
+        assert!(ts.whisper_list(server_id).is_empty());
+        assert!(handler.player_state(server_id, jane).is_none());
+    }
+
+    #[test]
+    fn test_whisper_list_follows_client_index() {
+        let ts = MockTeamSpeak::new();
+        let server_id = connect(&ts, "server-a");
+        let mut handler = GameHandler::new();
+
+        handler.initiate(&ts, initiate_parameter("server-a", "John Doe", 0));
+        apply_moves(&mut handler, &ts);
+        handler.add_radio_channel_member(&ts, "server-a", "Jane Doe", true);
+        handler.radio_communication_update(&ts, "server-a", "John Doe", false);
+        assert!(ts.whisper_list(server_id).is_empty());
+
+        // Jane connects after we started transmitting, first with her ts nickname
+        let jane = ts.add_client(server_id, "jane_ts", LOBBY);
+        handler.ts_on_client_updated(&ts, server_id, jane);
+        assert!(ts.whisper_list(server_id).is_empty());
+
+        ts.rename(server_id, jane, "Jane Doe");
+        handler.ts_on_client_updated(&ts, server_id, jane);
+        assert_eq!(ts.whisper_list(server_id), vec![jane]);
+
+        handler.ts_on_client_left(&ts, server_id, jane);
+        assert!(ts.whisper_list(server_id).is_empty());
+    }
+
+    #[test]
+    fn test_reset_stops_radio_transmission() {
+        let ts = MockTeamSpeak::new();
+        let server_id = connect(&ts, "server-a");
+        ts.add_client(server_id, "Jane Doe", GAME_CHANNEL);
+        let mut handler = GameHandler::new();
+
+        handler.initiate(&ts, initiate_parameter("server-a", "John Doe", 0));
+        apply_moves(&mut handler, &ts);
+        handler.add_radio_channel_member(&ts, "server-a", "Jane Doe", true);
+        handler.radio_communication_update(&ts, "server-a", "John Doe", false);
+        assert_eq!(ts.whisper_list(server_id).len(), 1);
+
+        handler.reset(&ts, "server-a");
+        assert!(!handler.is_transmitting_radio(server_id));
+        assert!(ts.whisper_list(server_id).is_empty());
+    }
+
+    #[test]
+    fn test_sessions_per_server() {
+        let ts = MockTeamSpeak::new();
+        let server_a = connect(&ts, "server-a");
+        let server_b = connect(&ts, "server-b");
+        let mut handler = GameHandler::new();
+
+        handler.initiate(&ts, initiate_parameter("server-a", "John Doe", 0));
+        handler.initiate(&ts, initiate_parameter("server-b", "Jane Doe", 0));
+        apply_moves(&mut handler, &ts);
+        assert_eq!(handler.active_instances(), 2);
+
+        move_own_client(&mut handler, &ts, server_a, SUPPORT_CHANNEL);
+        assert!(handler.is_voice_suspended(server_a));
+        assert!(!handler.is_voice_suspended(server_b));
+
+        ts.disconnect(server_a);
+        handler.ts_on_disconnected(server_a);
+        assert_eq!(handler.active_instances(), 1);
+    }
+
+    #[test]
+    fn test_fallback_nickname() {
+        assert_eq!(fallback_nickname("John Doe", 0), "John Doe");
+        assert_eq!(fallback_nickname("John Doe", 1), "John Doe (2)");
+        assert_eq!(fallback_nickname("John Doe", 2), "John Doe (3)");
+    }
+
+    #[test]
+    fn test_rejoin_delay_backs_off() {
+        assert_eq!(rejoin_delay(1), Duration::from_secs(1));
+        assert_eq!(rejoin_delay(2), Duration::from_secs(2));
+        assert_eq!(rejoin_delay(3), Duration::from_secs(4));
+        assert_eq!(rejoin_delay(REJOIN_MAX_ATTEMPTS + 10), REJOIN_MAX_DELAY);
+    }
+
+    #[test]
+    fn test_fallback_nickname_truncates() {
+        let nick = "A".repeat(MAX_NICKNAME_LENGTH);
+        let fallback = fallback_nickname(&nick, 1);
+
+        assert_eq!(fallback.chars().count(), MAX_NICKNAME_LENGTH);
+        assert!(fallback.ends_with(" (2)"));
     }
 }
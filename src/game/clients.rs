@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+
+use ts3plugin::{ConnectionId, ServerId};
+
+use crate::teamspeak::TeamSpeakBackend;
+
+// game names are the ts nicknames, so the index follows connects, renames and moves
+#[derive(Default)]
+pub struct ClientIndex {
+    by_name: HashMap<String, ConnectionId>,
+    by_connection: HashMap<ConnectionId, String>,
+}
+
+impl ClientIndex {
+    // every client currently visible on the server
+    pub fn from_backend(ts: &dyn TeamSpeakBackend, server_id: ServerId) -> Self {
+        let mut index = Self::default();
+        for client_id in ts.client_ids(server_id).unwrap_or_default() {
+            if let Ok(name) = ts.client_name(server_id, client_id) {
+                index.insert(client_id, &name);
+            }
+        }
+        index
+    }
+
+    // also used for renames, the previous name of the client is dropped
+    pub fn insert(&mut self, connection_id: ConnectionId, name: &str) {
+        self.remove(connection_id);
+        if let Some(previous) = self.by_name.insert(name.to_owned(), connection_id) {
+            self.by_connection.remove(&previous);
+        }
+        self.by_connection.insert(connection_id, name.to_owned());
+    }
+
+    pub fn remove(&mut self, connection_id: ConnectionId) {
+        if let Some(name) = self.by_connection.remove(&connection_id) {
+            self.by_name.remove(&name);
+        }
+    }
+
+    pub fn connection_id(&self, name: &str) -> Option<ConnectionId> {
+        self.by_name.get(name).copied()
+    }
+
+    pub fn name(&self, connection_id: ConnectionId) -> Option<&str> {
+        self.by_connection.get(&connection_id).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_follows_renames() {
+        let mut index = ClientIndex::default();
+        index.insert(ConnectionId(1), "Jane Doe");
+        index.insert(ConnectionId(2), "Max Mustermann");
+
+        index.insert(ConnectionId(1), "Erika Mustermann");
+        assert_eq!(index.connection_id("Jane Doe"), None);
+        assert_eq!(
+            index.connection_id("Erika Mustermann"),
+            Some(ConnectionId(1))
+        );
+        assert_eq!(index.name(ConnectionId(1)), Some("Erika Mustermann"));
+
+        // a name moving to another client replaces the stale entry
+        index.insert(ConnectionId(3), "Max Mustermann");
+        assert_eq!(index.name(ConnectionId(2)), None);
+        assert_eq!(index.connection_id("Max Mustermann"), Some(ConnectionId(3)));
+
+        index.remove(ConnectionId(3));
+        assert_eq!(index.connection_id("Max Mustermann"), None);
+        assert_eq!(index.name(ConnectionId(2)), None);
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::HashSet;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RadioChannel {
+    Primary,
+    Secondary,
+}
+
+impl RadioChannel {
+    pub fn from_primary(is_primary_channel: bool) -> Self {
+        if is_primary_channel {
+            Self::Primary
+        } else {
+            Self::Secondary
+        }
+    }
+
+    pub fn from_secondary(secondary: bool) -> Self {
+        Self::from_primary(!secondary)
+    }
+}
+
+// radio channels of the local player, members are kept by their game name
+#[derive(Default)]
+pub struct RadioState {
+    primary_members: HashSet<String>,
+    secondary_members: HashSet<String>,
+    transmitting: Option<RadioChannel>,
+    // players we hear over the radio right now
+    speakers: HashSet<String>,
+}
+
+impl RadioState {
+    pub fn members(&self, channel: RadioChannel) -> &HashSet<String> {
+        match channel {
+            RadioChannel::Primary => &self.primary_members,
+            RadioChannel::Secondary => &self.secondary_members,
+        }
+    }
+
+    fn members_mut(&mut self, channel: RadioChannel) -> &mut HashSet<String> {
+        match channel {
+            RadioChannel::Primary => &mut self.primary_members,
+            RadioChannel::Secondary => &mut self.secondary_members,
+        }
+    }
+
+    pub fn add_member(&mut self, channel: RadioChannel, name: &str) {
+        self.members_mut(channel).insert(name.to_owned());
+    }
+
+    pub fn remove_member(&mut self, channel: RadioChannel, name: &str) {
+        self.members_mut(channel).remove(name);
+    }
+
+    pub fn set_members(&mut self, channel: RadioChannel, names: Vec<String>) {
+        *self.members_mut(channel) = names.into_iter().collect();
+    }
+
+    pub fn transmitting(&self) -> Option<RadioChannel> {
+        self.transmitting
+    }
+
+    pub fn start_transmission(&mut self, channel: RadioChannel) {
+        self.transmitting = Some(channel);
+    }
+
+    pub fn stop_transmission(&mut self) {
+        self.transmitting = None;
+    }
+
+    pub fn start_receiving(&mut self, name: &str) {
+        self.speakers.insert(name.to_owned());
+    }
+
+    pub fn stop_receiving(&mut self, name: &str) {
+        self.speakers.remove(name);
+    }
+
+    pub fn is_receiving(&self, name: &str) -> bool {
+        self.speakers.contains(name)
+    }
+
+    // members that hear us right now, empty while not transmitting
+    pub fn listeners(&self) -> Option<&HashSet<String>> {
+        self.transmitting.map(|channel| self.members(channel))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listeners_follow_transmission() {
+        let mut radio = RadioState::default();
+        radio.set_members(
+            RadioChannel::Primary,
+            vec!["Jane Doe".to_owned(), "Max Mustermann".to_owned()],
+        );
+        radio.add_member(RadioChannel::Secondary, "Erika Mustermann");
+        assert_eq!(radio.listeners(), None);
+
+        radio.start_transmission(RadioChannel::from_secondary(true));
+        let listeners = radio.listeners().unwrap();
+        assert_eq!(listeners.len(), 1);
+        assert!(listeners.contains("Erika Mustermann"));
+
+        radio.start_transmission(RadioChannel::Primary);
+        radio.remove_member(RadioChannel::Primary, "Max Mustermann");
+        let listeners = radio.listeners().unwrap();
+        assert_eq!(listeners.len(), 1);
+        assert!(listeners.contains("Jane Doe"));
+
+        radio.stop_transmission();
+        assert_eq!(radio.listeners(), None);
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::websocket::protocol::{PlayerStateUpdateParameter, SelfStateUpdateParameter, Vector3};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TargetReason {
+    Proximity,
+    Radio,
+    Phone,
+}
+
+// players that should hear the local player, kept by game name together with why
+#[derive(Default)]
+pub struct TargetManager {
+    targets: HashMap<String, HashSet<TargetReason>>,
+}
+
+impl TargetManager {
+    // the add, remove and set calls return whether the set of targeted players changed
+    pub fn add(&mut self, reason: TargetReason, name: &str) -> bool {
+        let reasons = self.targets.entry(name.to_owned()).or_default();
+        reasons.insert(reason) && reasons.len() == 1
+    }
+
+    pub fn remove(&mut self, reason: TargetReason, name: &str) -> bool {
+        let reasons = match self.targets.get_mut(name) {
+            Some(reasons) => reasons,
+            None => return false,
+        };
+        if !reasons.remove(&reason) || !reasons.is_empty() {
+            return false;
+        }
+
+        self.targets.remove(name);
+        true
+    }
+
+    // replaces every target added for the reason
+    pub fn set(&mut self, reason: TargetReason, names: &HashSet<String>) -> bool {
+        let stale: Vec<String> = self
+            .targets
+            .iter()
+            .filter(|(name, reasons)| reasons.contains(&reason) && !names.contains(*name))
+            .map(|(name, _)| name.to_owned())
+            .collect();
+
+        let mut changed = false;
+        for name in stale {
+            changed |= self.remove(reason, &name);
+        }
+        for name in names {
+            changed |= self.add(reason, name);
+        }
+        changed
+    }
+
+    pub fn contains(&self, reason: TargetReason, name: &str) -> bool {
+        self.targets
+            .get(name)
+            .map(|reasons| reasons.contains(&reason))
+            .unwrap_or(false)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.targets.keys()
+    }
+}
+
+// players within our voice range, dead players talk to nobody
+pub fn players_in_range<'a>(
+    self_state: &SelfStateUpdateParameter,
+    players: impl IntoIterator<Item = &'a PlayerStateUpdateParameter>,
+) -> HashSet<String> {
+    if !self_state.is_alive {
+        return HashSet::new();
+    }
+
+    players
+        .into_iter()
+        .filter(|player| !player.distance_culled)
+        .filter(|player| distance(&self_state.position, &player.position) <= self_state.voice_range)
+        .map(|player| player.name.to_owned())
+        .collect()
+}
+
+fn distance(a: &Vector3, b: &Vector3) -> f32 {
+    ((a.x - b.x).powi(2) + (a.y - b.y).powi(2) + (a.z - b.z).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn targeted(targets: &TargetManager) -> HashSet<String> {
+        targets.names().cloned().collect()
+    }
+
+    #[test]
+    fn test_targets_are_kept_while_any_reason_remains() {
+        let mut targets = TargetManager::default();
+
+        assert!(targets.add(TargetReason::Proximity, "Jane Doe"));
+        assert!(!targets.add(TargetReason::Radio, "Jane Doe"));
+        assert!(!targets.remove(TargetReason::Proximity, "Jane Doe"));
+        assert_eq!(targeted(&targets), names(&["Jane Doe"]));
+
+        assert!(!targets.remove(TargetReason::Phone, "Jane Doe"));
+        assert!(targets.remove(TargetReason::Radio, "Jane Doe"));
+        assert_eq!(targets.names().count(), 0);
+    }
+
+    #[test]
+    fn test_set_only_reports_changes() {
+        let mut targets = TargetManager::default();
+        targets.add(TargetReason::Phone, "Max Mustermann");
+
+        assert!(targets.set(
+            TargetReason::Proximity,
+            &names(&["Jane Doe", "Max Mustermann"])
+        ));
+        assert!(!targets.set(
+            TargetReason::Proximity,
+            &names(&["Jane Doe", "Max Mustermann"])
+        ));
+
+        // Max is still on the phone with us
+        assert!(!targets.set(TargetReason::Proximity, &names(&["Jane Doe"])));
+        assert!(targets.set(TargetReason::Proximity, &names(&[])));
+        assert_eq!(targeted(&targets), names(&["Max Mustermann"]));
+    }
+
+    #[test]
+    fn test_players_in_range() {
+        let self_state: SelfStateUpdateParameter = serde_json::from_value(serde_json::json!({
+            "Position": { "X": 0.0, "Y": 0.0, "Z": 0.0 },
+            "Rotation": 0.0,
+            "VoiceRange": 8.0,
+            "Echo": null,
+        }))
+        .unwrap();
+        let player = |name: &str, x: f32, distance_culled: bool| -> PlayerStateUpdateParameter {
+            serde_json::from_value(serde_json::json!({
+                "Name": name,
+                "Position": { "X": x, "Y": 0.0, "Z": 0.0 },
+                "Rotation": 0.0,
+                "VoiceRange": 8.0,
+                "VolumeOverride": null,
+                "DistanceCulled": distance_culled,
+                "Muffle": null,
+            }))
+            .unwrap()
+        };
+        let players = [
+            player("Jane Doe", 8.0, false),
+            player("Max Mustermann", 8.5, false),
+            player("Erika Mustermann", 1.0, true),
+        ];
+
+        assert_eq!(
+            players_in_range(&self_state, &players),
+            names(&["Jane Doe"])
+        );
+    }
+}
@@ -107,7 +107,7 @@ impl Application for Settings {
         (me, Command::none())
     }
 
-    fn view(&self) -> Element<Message> {
+    fn view(&self) -> Element<'_, Message> {
         let content = column![
             text("WebSocket URL:"),
             text_input(&self.web_socket_address, &self.web_socket_address)
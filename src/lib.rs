@@ -1,22 +1,27 @@
-mod audiofx;
+pub mod audiofx;
 mod game;
 mod gui;
+mod teamspeak;
 mod websocket;
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
 use anyhow::Result;
+use audiofx::{AudioBuffer, CommunicationType, EffectChain};
 use game::GameHandler;
-use iir_filters::filter::DirectForm2Transposed;
 use ts3plugin::*;
 
 #[macro_use]
 extern crate lazy_static;
 
 struct RustyChatTsPlugin {
-    low_pass: DirectForm2Transposed,
-    band_pass: DirectForm2Transposed,
-    high_pass: DirectForm2Transposed,
-    vol_follow: f32,
+    // built once, the audio callbacks only take fresh copies of them
+    presets: HashMap<CommunicationType, EffectChain>,
+    // radio effect on our own voice while we transmit
+    outgoing_effects: EffectChain,
+    // effects on the voices we hear, every speaker keeps the state of its chain
+    incoming_effects: HashMap<(ServerId, ConnectionId), (CommunicationType, EffectChain)>,
+    audio_buffer: AudioBuffer,
     rusty_handler: Arc<Mutex<GameHandler>>,
 }
 
@@ -56,21 +61,54 @@ impl Plugin for RustyChatTsPlugin {
     fn connect_status_change(
         &mut self,
         _api: &mut TsApi,
-        _server_id: ServerId,
+        server_id: ServerId,
         status: ConnectStatus,
         _error: Error,
     ) {
-        match status {
-            ConnectStatus::Connected => {
-                //self.rusty_handler.lock().unwrap().server_id = Some(server_id.0);
-            }
-            _ => {}
+        if let ConnectStatus::Disconnected = status {
+            self.incoming_effects
+                .retain(|(server, _), _| *server != server_id);
+            self.rusty_handler
+                .lock()
+                .unwrap()
+                .ts_on_disconnected(server_id);
         }
     }
 
+    fn connection_changed(
+        &mut self,
+        api: &mut TsApi,
+        server_id: ServerId,
+        connection_id: ConnectionId,
+        connected: bool,
+        _message: String,
+    ) {
+        let visibility = if connected {
+            Visibility::Enter
+        } else {
+            Visibility::Leave
+        };
+        self.on_client_visibility(api, server_id, connection_id, visibility);
+    }
+
+    fn connection_updated(
+        &mut self,
+        api: &mut TsApi,
+        server_id: ServerId,
+        connection_id: ConnectionId,
+        _old_connection: Option<Connection>,
+        _invoker: Invoker,
+    ) {
+        self.rusty_handler.lock().unwrap().ts_on_client_updated(
+            &teamspeak::Ts3Backend::new(api),
+            server_id,
+            connection_id,
+        );
+    }
+
     fn connection_move(
         &mut self,
-        _api: &mut TsApi,
+        api: &mut TsApi,
         server_id: ServerId,
         connection_id: ConnectionId,
         _old_channel_id: ChannelId,
@@ -79,16 +117,18 @@ impl Plugin for RustyChatTsPlugin {
     ) {
         println!("move");
         self.rusty_handler.lock().unwrap().ts_on_channel_switched(
+            &teamspeak::Ts3Backend::new(api),
             server_id,
             connection_id,
             new_channel_id,
             visibility,
         );
+        self.on_client_visibility(api, server_id, connection_id, visibility);
     }
 
     fn connection_moved(
         &mut self,
-        _api: &mut TsApi,
+        api: &mut TsApi,
         server_id: ServerId,
         connection_id: ConnectionId,
         _old_channel_id: ChannelId,
@@ -98,41 +138,73 @@ impl Plugin for RustyChatTsPlugin {
     ) {
         println!("moved");
         self.rusty_handler.lock().unwrap().ts_on_channel_switched(
+            &teamspeak::Ts3Backend::new(api),
             server_id,
             connection_id,
             new_channel_id,
             visibility,
         );
+        self.on_client_visibility(api, server_id, connection_id, visibility);
+    }
+
+    fn channel_kick(
+        &mut self,
+        api: &mut TsApi,
+        server_id: ServerId,
+        connection_id: ConnectionId,
+        _old_channel_id: ChannelId,
+        new_channel_id: ChannelId,
+        visibility: Visibility,
+        _invoker: Invoker,
+        _message: String,
+    ) {
+        self.rusty_handler.lock().unwrap().ts_on_channel_switched(
+            &teamspeak::Ts3Backend::new(api),
+            server_id,
+            connection_id,
+            new_channel_id,
+            visibility,
+        );
+        self.on_client_visibility(api, server_id, connection_id, visibility);
     }
 
     fn new(api: &mut TsApi) -> Result<Box<Self>, InitError> {
         api.log_or_print("Inited", "RustyChatTsPlugin", LogLevel::Info);
 
-        if win32console::console::WinConsole::alloc_console().is_err() {}
+        // fails when the client already has a console
+        let _ = win32console::console::WinConsole::alloc_console();
 
         println!("attached console");
 
-        let game_inst = GameHandler {
-            server_id: None,
-            in_game: false,
-            original_channel: None,
-            game_channel: None,
-            own_client_id: None,
-        };
+        let game_inst = GameHandler::new();
 
         let game_ref = Arc::new(Mutex::new(game_inst));
 
         websocket::start_listen(game_ref.clone());
+        game::start_rejoin_watcher(game_ref.clone());
 
-        let low_pass = audiofx::init_lowpass().unwrap();
-        let band_pass = audiofx::init_band_pass().unwrap();
-        let high_pass = audiofx::init_high_pass().unwrap();
+        let presets = CommunicationType::ALL
+            .into_iter()
+            .map(|communication| {
+                EffectChain::preset(communication, audiofx::SAMPLE_RATE)
+                    .map(|effects| (communication, effects))
+            })
+            .collect::<Result<HashMap<_, _>>>()
+            .map_err(|err| {
+                api.log_or_print(
+                    format!("failed to create effects: {}", err),
+                    "RustyChatTsPlugin",
+                    LogLevel::Error,
+                );
+                InitError::Failure
+            })?;
+        let outgoing_effects = presets[&CommunicationType::Radio].fresh_chain(rand::random());
 
         Ok(Box::new(Self {
-            low_pass,
-            band_pass,
-            high_pass,
-            vol_follow: 0.0,
+            presets,
+            outgoing_effects,
+            incoming_effects: HashMap::new(),
+            audio_buffer: AudioBuffer::default(),
             rusty_handler: game_ref.clone(),
         }))
     }
@@ -140,25 +212,75 @@ impl Plugin for RustyChatTsPlugin {
     fn post_process_voice_data(
         &mut self,
         _api: &mut TsApi,
-        _server_id: ServerId,
-        _connection_id: ConnectionId,
+        server_id: ServerId,
+        connection_id: ConnectionId,
         samples: &mut [i16],
-        _channels: i32,
+        channels: i32,
         _channel_speaker_array: &[Speaker],
         _channel_fill_mask: &mut u32,
     ) {
-        audiofx::process_radio(samples, &mut self.vol_follow);
+        let (communication, volume) = {
+            let game = self.rusty_handler.lock().unwrap();
+            if game.is_voice_suspended(server_id) {
+                return;
+            }
+            let volume = game
+                .player_state(server_id, connection_id)
+                .and_then(|player| player.volume_override);
+            (
+                game.incoming_communication(server_id, connection_id),
+                volume,
+            )
+        };
+        let channels = channels.max(1) as usize;
+
+        let speaker = (server_id, connection_id);
+        let effects = speaker_effects(
+            &mut self.incoming_effects,
+            &self.presets,
+            speaker,
+            communication,
+        );
+        if effects.is_none() && volume.is_none() {
+            return;
+        }
+
+        // the game raises or lowers single players, in the same pass as the effects
+        let mut no_effects = EffectChain::default();
+        self.audio_buffer.process_with_gain(
+            effects.unwrap_or(&mut no_effects),
+            volume.unwrap_or(1.0),
+            samples,
+            channels,
+        );
     }
 
     fn captured_voice_data(
         &mut self,
         _api: &mut TsApi,
-        _server_id: ServerId,
+        server_id: ServerId,
         samples: &mut [i16],
-        _channels: i32,
-        _send: &mut bool,
+        channels: i32,
+        send: &mut bool,
     ) -> bool {
-        audiofx::process_radio(samples, &mut self.vol_follow);
+        let game = self.rusty_handler.lock().unwrap();
+        if game.is_voice_suspended(server_id) {
+            return false;
+        }
+
+        if !game.has_listeners(server_id) {
+            *send = false;
+            return false;
+        }
+
+        if !game.is_transmitting_radio(server_id) {
+            return false;
+        }
+        self.audio_buffer.process(
+            &mut self.outgoing_effects,
+            samples,
+            channels.max(1) as usize,
+        );
         true
     }
 
@@ -170,30 +292,36 @@ impl Plugin for RustyChatTsPlugin {
         talking: TalkStatus,
         _whispering: bool,
     ) {
-        let is_talking = match talking {
-            TalkStatus::NotTalking => false,
-            _ => true,
-        };
+        let is_talking = !matches!(talking, TalkStatus::NotTalking);
 
-        let server = api.get_server(server_id).unwrap();
-        let client = server.get_connection(connection_id).unwrap();
-        println!("Is Talking: {}, {}", client.get_id().0, is_talking);
-        let _ = websocket::on_talk_state_toggle(
-            server.get_uid().unwrap(),
+        self.rusty_handler.lock().unwrap().ts_on_talking_changed(
+            &teamspeak::Ts3Backend::new(api),
+            server_id,
+            connection_id,
             is_talking,
-            client.get_name().unwrap(),
         );
     }
 
     fn self_variable_update(
         &mut self,
-        _api: &mut TsApi,
+        api: &mut TsApi,
         server_id: ServerId,
         flag: ClientProperties,
-        old_value: String,
-        new_value: String,
+        _old_value: String,
+        _new_value: String,
     ) {
-        websocket::on_self_variable_update(server_id, flag, old_value, new_value);
+        if matches!(
+            flag,
+            ClientProperties::InputMuted
+                | ClientProperties::OutputMuted
+                | ClientProperties::InputHardware
+                | ClientProperties::OutputHardware
+        ) {
+            self.rusty_handler
+                .lock()
+                .unwrap()
+                .ts_on_sound_state_changed(&teamspeak::Ts3Backend::new(api), server_id);
+        }
     }
 
     fn shutdown(&mut self, api: &mut TsApi) {
@@ -202,4 +330,56 @@ impl Plugin for RustyChatTsPlugin {
     }
 }
 
+impl RustyChatTsPlugin {
+    // keeps the game name index in sync with the clients we can see
+    fn on_client_visibility(
+        &mut self,
+        api: &TsApi,
+        server_id: ServerId,
+        connection_id: ConnectionId,
+        visibility: Visibility,
+    ) {
+        let ts = teamspeak::Ts3Backend::new(api);
+        let mut game = self.rusty_handler.lock().unwrap();
+        match visibility {
+            Visibility::Enter => game.ts_on_client_updated(&ts, server_id, connection_id),
+            Visibility::Leave => {
+                self.incoming_effects.remove(&(server_id, connection_id));
+                game.ts_on_client_left(&ts, server_id, connection_id);
+            }
+            Visibility::Retain => {}
+        }
+    }
+}
+
+// the chain for how the speaker reaches us, none for plain proximity,
+// a speaker switching from radio to phone starts with a fresh chain
+fn speaker_effects<'a>(
+    incoming_effects: &'a mut HashMap<(ServerId, ConnectionId), (CommunicationType, EffectChain)>,
+    presets: &HashMap<CommunicationType, EffectChain>,
+    speaker: (ServerId, ConnectionId),
+    communication: CommunicationType,
+) -> Option<&'a mut EffectChain> {
+    if communication == CommunicationType::Proximity {
+        incoming_effects.remove(&speaker);
+        return None;
+    }
+
+    let stale = incoming_effects
+        .get(&speaker)
+        .map(|(current, _)| *current != communication)
+        .unwrap_or(true);
+    if stale {
+        // every speaker gets its own noise
+        let (server_id, connection_id) = speaker;
+        let seed = server_id.0 << 16 | connection_id.0 as u64;
+        let effects = presets.get(&communication)?.fresh_chain(seed);
+        incoming_effects.insert(speaker, (communication, effects));
+    }
+
+    incoming_effects
+        .get_mut(&speaker)
+        .map(|(_, effects)| effects)
+}
+
 create_plugin!(RustyChatTsPlugin);
@@ -0,0 +1,307 @@
+#[cfg(test)]
+pub mod mock;
+
+use std::ffi::{c_char, CString};
+
+use anyhow::{anyhow, Result};
+use ts3plugin::{ChannelId, ConnectionId, LogLevel, ServerId, TsApi};
+
+// ts3 client property and error codes used with the raw api
+const CLIENT_NICKNAME: usize = 1;
+const CLIENT_FLAG_TALKING: usize = 4;
+const CLIENT_INPUT_MUTED: usize = 5;
+const CLIENT_OUTPUT_MUTED: usize = 6;
+const CLIENT_INPUT_HARDWARE: usize = 8;
+const CLIENT_OUTPUT_HARDWARE: usize = 9;
+const ERROR_OK: u32 = 0x0000;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SoundState {
+    pub input_muted: bool,
+    pub output_muted: bool,
+    pub input_hardware_enabled: bool,
+    pub output_hardware_enabled: bool,
+}
+
+// everything the game logic needs from the teamspeak client
+pub trait TeamSpeakBackend {
+    fn server_by_uid(&self, uid: &str) -> Option<ServerId>;
+    fn own_client_id(&self, server_id: ServerId) -> Result<ConnectionId>;
+    fn client_ids(&self, server_id: ServerId) -> Result<Vec<ConnectionId>>;
+    fn client_name(&self, server_id: ServerId, client_id: ConnectionId) -> Result<String>;
+    fn client_channel(&self, server_id: ServerId, client_id: ConnectionId) -> Result<ChannelId>;
+    fn channel_exists(&self, server_id: ServerId, channel_id: ChannelId) -> bool;
+    fn rename_self(&self, server_id: ServerId, nick: &str) -> Result<()>;
+    fn move_client(
+        &self,
+        server_id: ServerId,
+        client_id: ConnectionId,
+        channel_id: ChannelId,
+    ) -> Result<()>;
+    fn sound_state(&self, server_id: ServerId) -> Result<SoundState>;
+    // mute, deafen and talk status, no game command drives them yet
+    #[allow(dead_code)]
+    fn set_input_muted(&self, server_id: ServerId, muted: bool) -> Result<()>;
+    #[allow(dead_code)]
+    fn set_output_muted(&self, server_id: ServerId, muted: bool) -> Result<()>;
+    #[allow(dead_code)]
+    fn is_talking(&self, server_id: ServerId, client_id: ConnectionId) -> Result<bool>;
+    // our voice only reaches the given clients, an empty list talks to the channel again
+    fn set_whisper_list(&self, server_id: ServerId, client_ids: &[ConnectionId]) -> Result<()>;
+    fn log(&self, message: &str, level: LogLevel);
+
+    fn is_nickname_in_use(&self, server_id: ServerId, nick: &str) -> Result<bool> {
+        let own_client_id = self.own_client_id(server_id)?;
+        let nick = nick.to_lowercase();
+
+        Ok(self
+            .client_ids(server_id)?
+            .into_iter()
+            .filter(|client_id| *client_id != own_client_id)
+            .any(|client_id| {
+                self.client_name(server_id, client_id)
+                    .map(|name| name.to_lowercase() == nick)
+                    .unwrap_or(false)
+            }))
+    }
+}
+
+// hands out a backend to threads running outside of the plugin callbacks
+pub trait BackendProvider: Send + Sync {
+    fn with_backend(&self, f: &mut dyn FnMut(&dyn TeamSpeakBackend));
+}
+
+pub struct Ts3Provider;
+
+impl BackendProvider for Ts3Provider {
+    fn with_backend(&self, f: &mut dyn FnMut(&dyn TeamSpeakBackend)) {
+        with_ts_api(|ts| f(ts));
+    }
+}
+
+// locks the ts api and runs `f` with the ts3plugin backend,
+// must not be called from within a plugin callback
+pub fn with_ts_api<R>(f: impl FnOnce(&Ts3Backend) -> R) -> Option<R> {
+    let ts_api = TsApi::lock_api()?;
+    Some(f(&Ts3Backend::new(&ts_api)))
+}
+
+pub struct Ts3Backend<'a> {
+    api: &'a TsApi,
+}
+
+impl<'a> Ts3Backend<'a> {
+    pub fn new(api: &'a TsApi) -> Self {
+        Self { api }
+    }
+
+    fn get_self_flag(&self, server_id: ServerId, flag: usize) -> Result<bool> {
+        let mut value = 0;
+        let error = unsafe {
+            let raw_api: &ts3plugin::Ts3Functions = self.api.get_raw_api();
+            (raw_api.get_client_self_variable_as_int)(server_id.0, flag as _, &mut value)
+        };
+
+        check_error(error)?;
+        Ok(value != 0)
+    }
+
+    fn set_self_flag(&self, server_id: ServerId, flag: usize, enabled: bool) -> Result<()> {
+        let error = unsafe {
+            let raw_api: &ts3plugin::Ts3Functions = self.api.get_raw_api();
+            (raw_api.set_client_self_variable_as_int)(server_id.0, flag as _, enabled as _)
+        };
+
+        check_error(error)?;
+        self.flush_self_updates(server_id)
+    }
+
+    fn flush_self_updates(&self, server_id: ServerId) -> Result<()> {
+        let error = unsafe {
+            let raw_api: &ts3plugin::Ts3Functions = self.api.get_raw_api();
+            (raw_api.flush_client_self_updates)(server_id.0, std::ptr::null())
+        };
+
+        check_error(error)
+    }
+}
+
+impl<'a> TeamSpeakBackend for Ts3Backend<'a> {
+    fn server_by_uid(&self, uid: &str) -> Option<ServerId> {
+        self.api.get_server_ids().into_iter().find(|server_id| {
+            self.api
+                .get_server(*server_id)
+                .and_then(|server| server.get_uid().ok())
+                .map(|server_uid| server_uid == uid)
+                .unwrap_or(false)
+        })
+    }
+
+    fn own_client_id(&self, server_id: ServerId) -> Result<ConnectionId> {
+        self.api
+            .get_server(server_id)
+            .ok_or(anyhow!("server {} not found", server_id.0))?
+            .get_own_connection_id()
+            .map_err(|err| anyhow!("own client not found: {:?}", err))
+    }
+
+    fn client_ids(&self, server_id: ServerId) -> Result<Vec<ConnectionId>> {
+        let mut list: *mut u16 = std::ptr::null_mut();
+        let error = unsafe {
+            let raw_api: &ts3plugin::Ts3Functions = self.api.get_raw_api();
+            (raw_api.get_client_list)(server_id.0, &mut list)
+        };
+        check_error(error)?;
+
+        // the list is zero terminated and has to be freed by the ts client
+        let mut client_ids = Vec::new();
+        unsafe {
+            let mut client = list;
+            while *client != 0 {
+                client_ids.push(ConnectionId(*client));
+                client = client.add(1);
+            }
+            let raw_api: &ts3plugin::Ts3Functions = self.api.get_raw_api();
+            (raw_api.free_memory)(list as *mut _);
+        }
+
+        Ok(client_ids)
+    }
+
+    fn client_name(&self, server_id: ServerId, client_id: ConnectionId) -> Result<String> {
+        self.api
+            .get_server(server_id)
+            .and_then(|server| server.get_connection(client_id))
+            .ok_or(anyhow!("client {} not found", client_id.0))?
+            .get_name()
+            .map(|name| name.to_owned())
+            .map_err(|err| anyhow!("client name not available: {:?}", err))
+    }
+
+    fn client_channel(&self, server_id: ServerId, client_id: ConnectionId) -> Result<ChannelId> {
+        let mut channel_id = 0;
+        let error = unsafe {
+            let raw_api: &ts3plugin::Ts3Functions = self.api.get_raw_api();
+            (raw_api.get_channel_of_client)(server_id.0, client_id.0, &mut channel_id)
+        };
+
+        check_error(error)?;
+        Ok(ChannelId(channel_id))
+    }
+
+    fn channel_exists(&self, server_id: ServerId, channel_id: ChannelId) -> bool {
+        self.api
+            .get_server(server_id)
+            .and_then(|server| server.get_channel(channel_id))
+            .is_some()
+    }
+
+    // a taken nickname is only reported later by the server, so callers have to check
+    // `is_nickname_in_use` first
+    fn rename_self(&self, server_id: ServerId, nick: &str) -> Result<()> {
+        let name = CString::new(nick)?;
+        let error = unsafe {
+            let raw_api: &ts3plugin::Ts3Functions = self.api.get_raw_api();
+            (raw_api.set_client_self_variable_as_string)(
+                server_id.0,
+                CLIENT_NICKNAME as _,
+                name.as_ptr() as *const c_char,
+            )
+        };
+
+        check_error(error)?;
+        self.flush_self_updates(server_id)
+    }
+
+    fn move_client(
+        &self,
+        server_id: ServerId,
+        client_id: ConnectionId,
+        channel_id: ChannelId,
+    ) -> Result<()> {
+        let empty = CString::default();
+        let error = unsafe {
+            let raw_api: &ts3plugin::Ts3Functions = self.api.get_raw_api();
+            (raw_api.request_client_move)(
+                server_id.0,
+                client_id.0,
+                channel_id.0,
+                empty.as_ptr(),
+                empty.as_ptr(),
+            )
+        };
+
+        check_error(error)
+    }
+
+    fn sound_state(&self, server_id: ServerId) -> Result<SoundState> {
+        Ok(SoundState {
+            input_muted: self.get_self_flag(server_id, CLIENT_INPUT_MUTED)?,
+            output_muted: self.get_self_flag(server_id, CLIENT_OUTPUT_MUTED)?,
+            input_hardware_enabled: self.get_self_flag(server_id, CLIENT_INPUT_HARDWARE)?,
+            output_hardware_enabled: self.get_self_flag(server_id, CLIENT_OUTPUT_HARDWARE)?,
+        })
+    }
+
+    fn set_input_muted(&self, server_id: ServerId, muted: bool) -> Result<()> {
+        self.set_self_flag(server_id, CLIENT_INPUT_MUTED, muted)
+    }
+
+    fn set_output_muted(&self, server_id: ServerId, muted: bool) -> Result<()> {
+        self.set_self_flag(server_id, CLIENT_OUTPUT_MUTED, muted)
+    }
+
+    fn is_talking(&self, server_id: ServerId, client_id: ConnectionId) -> Result<bool> {
+        let mut talking = 0;
+        let error = unsafe {
+            let raw_api: &ts3plugin::Ts3Functions = self.api.get_raw_api();
+            (raw_api.get_client_variable_as_int)(
+                server_id.0,
+                client_id.0,
+                CLIENT_FLAG_TALKING as _,
+                &mut talking,
+            )
+        };
+
+        check_error(error)?;
+        Ok(talking != 0)
+    }
+
+    fn set_whisper_list(&self, server_id: ServerId, client_ids: &[ConnectionId]) -> Result<()> {
+        // the client ids are passed as a zero terminated array, null clears the list
+        let targets: Vec<u16> = client_ids
+            .iter()
+            .map(|client_id| client_id.0)
+            .chain(std::iter::once(0))
+            .collect();
+        let targets_ptr = if client_ids.is_empty() {
+            std::ptr::null()
+        } else {
+            targets.as_ptr()
+        };
+
+        let error = unsafe {
+            let raw_api: &ts3plugin::Ts3Functions = self.api.get_raw_api();
+            (raw_api.request_client_set_whisper_list)(
+                server_id.0,
+                0,
+                std::ptr::null(),
+                targets_ptr,
+                std::ptr::null(),
+            )
+        };
+
+        check_error(error)
+    }
+
+    fn log(&self, message: &str, level: LogLevel) {
+        self.api.log_or_print(message, "RustyChat", level);
+    }
+}
+
+fn check_error(error: u32) -> Result<()> {
+    match error {
+        ERROR_OK => Ok(()),
+        error => Err(anyhow!("ts3 returned error {:#06x}", error)),
+    }
+}
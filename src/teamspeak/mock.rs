@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Result};
+use ts3plugin::{ChannelId, ConnectionId, LogLevel, ServerId};
+
+use super::{BackendProvider, SoundState, TeamSpeakBackend};
+
+// in-memory teamspeak client used to run game scenarios without a running ts3 client
+#[derive(Default)]
+pub struct MockTeamSpeak {
+    state: Mutex<MockState>,
+}
+
+#[derive(Default)]
+struct MockState {
+    servers: HashMap<ServerId, MockServer>,
+    next_server_id: u64,
+    moves: Vec<MockMove>,
+    logs: Vec<String>,
+}
+
+struct MockServer {
+    uid: String,
+    own_client_id: ConnectionId,
+    clients: HashMap<ConnectionId, MockClient>,
+    channels: HashMap<ChannelId, String>,
+    sound_state: SoundState,
+    next_client_id: u16,
+    whisper_list: Vec<ConnectionId>,
+    whisper_list_failing: bool,
+}
+
+struct MockClient {
+    name: String,
+    channel_id: ChannelId,
+    talking: bool,
+}
+
+// a move requested through the backend, the test has to feed it back into the
+// game handler like the ts3 client would with connection_move
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MockMove {
+    pub server_id: ServerId,
+    pub client_id: ConnectionId,
+    pub channel_id: ChannelId,
+}
+
+impl MockTeamSpeak {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // connects the own client to a new server, sitting in the given channel
+    pub fn connect(&self, uid: &str, own_name: &str, channel_id: ChannelId) -> ServerId {
+        let mut state = self.state.lock().unwrap();
+        state.next_server_id += 1;
+        let server_id = ServerId(state.next_server_id);
+
+        let mut server = MockServer {
+            uid: uid.to_owned(),
+            own_client_id: ConnectionId(0),
+            clients: HashMap::new(),
+            channels: HashMap::new(),
+            sound_state: SoundState {
+                input_hardware_enabled: true,
+                output_hardware_enabled: true,
+                ..SoundState::default()
+            },
+            next_client_id: 1,
+            whisper_list: Vec::new(),
+            whisper_list_failing: false,
+        };
+        server.channels.insert(channel_id, "Default".to_owned());
+        server.own_client_id = server.add_client(own_name, channel_id);
+
+        state.servers.insert(server_id, server);
+        server_id
+    }
+
+    pub fn disconnect(&self, server_id: ServerId) {
+        self.state.lock().unwrap().servers.remove(&server_id);
+    }
+
+    pub fn add_channel(&self, server_id: ServerId, channel_id: ChannelId, name: &str) {
+        self.with_server(server_id, |server| {
+            server.channels.insert(channel_id, name.to_owned());
+        });
+    }
+
+    pub fn add_client(
+        &self,
+        server_id: ServerId,
+        name: &str,
+        channel_id: ChannelId,
+    ) -> ConnectionId {
+        self.with_server(server_id, |server| server.add_client(name, channel_id))
+    }
+
+    // moves a client without going through the backend, e.g. an admin moving us
+    pub fn force_move(&self, server_id: ServerId, client_id: ConnectionId, channel_id: ChannelId) {
+        self.with_server(server_id, |server| {
+            server.clients.get_mut(&client_id).unwrap().channel_id = channel_id;
+        });
+    }
+
+    // renames another client, the own client is renamed through the backend
+    pub fn rename(&self, server_id: ServerId, client_id: ConnectionId, name: &str) {
+        self.with_server(server_id, |server| {
+            server.clients.get_mut(&client_id).unwrap().name = name.to_owned();
+        });
+    }
+
+    // makes the ts client reject every whisper list until reset
+    pub fn set_whisper_list_failing(&self, server_id: ServerId, failing: bool) {
+        self.with_server(server_id, |server| server.whisper_list_failing = failing);
+    }
+
+    pub fn whisper_list(&self, server_id: ServerId) -> Vec<ConnectionId> {
+        self.with_server(server_id, |server| server.whisper_list.clone())
+    }
+
+    pub fn take_moves(&self) -> Vec<MockMove> {
+        std::mem::take(&mut self.state.lock().unwrap().moves)
+    }
+
+    pub fn logs(&self) -> Vec<String> {
+        self.state.lock().unwrap().logs.clone()
+    }
+
+    fn with_server<R>(&self, server_id: ServerId, f: impl FnOnce(&mut MockServer) -> R) -> R {
+        let mut state = self.state.lock().unwrap();
+        f(state
+            .servers
+            .get_mut(&server_id)
+            .expect("mock server not connected"))
+    }
+
+    fn try_with_server<R>(
+        &self,
+        server_id: ServerId,
+        f: impl FnOnce(&mut MockServer) -> Result<R>,
+    ) -> Result<R> {
+        let mut state = self.state.lock().unwrap();
+        f(state
+            .servers
+            .get_mut(&server_id)
+            .ok_or(anyhow!("server {} not found", server_id.0))?)
+    }
+}
+
+impl MockServer {
+    fn add_client(&mut self, name: &str, channel_id: ChannelId) -> ConnectionId {
+        let client_id = ConnectionId(self.next_client_id);
+        self.next_client_id += 1;
+        self.clients.insert(
+            client_id,
+            MockClient {
+                name: name.to_owned(),
+                channel_id,
+                talking: false,
+            },
+        );
+        client_id
+    }
+
+    fn client(&mut self, client_id: ConnectionId) -> Result<&mut MockClient> {
+        self.clients
+            .get_mut(&client_id)
+            .ok_or(anyhow!("client {} not found", client_id.0))
+    }
+}
+
+impl TeamSpeakBackend for MockTeamSpeak {
+    fn server_by_uid(&self, uid: &str) -> Option<ServerId> {
+        self.state
+            .lock()
+            .unwrap()
+            .servers
+            .iter()
+            .find(|(_, server)| server.uid == uid)
+            .map(|(server_id, _)| *server_id)
+    }
+
+    fn own_client_id(&self, server_id: ServerId) -> Result<ConnectionId> {
+        self.try_with_server(server_id, |server| Ok(server.own_client_id))
+    }
+
+    fn client_ids(&self, server_id: ServerId) -> Result<Vec<ConnectionId>> {
+        self.try_with_server(server_id, |server| {
+            Ok(server.clients.keys().cloned().collect())
+        })
+    }
+
+    fn client_name(&self, server_id: ServerId, client_id: ConnectionId) -> Result<String> {
+        self.try_with_server(server_id, |server| {
+            Ok(server.client(client_id)?.name.clone())
+        })
+    }
+
+    fn client_channel(&self, server_id: ServerId, client_id: ConnectionId) -> Result<ChannelId> {
+        self.try_with_server(server_id, |server| Ok(server.client(client_id)?.channel_id))
+    }
+
+    fn channel_exists(&self, server_id: ServerId, channel_id: ChannelId) -> bool {
+        self.try_with_server(server_id, |server| {
+            Ok(server.channels.contains_key(&channel_id))
+        })
+        .unwrap_or(false)
+    }
+
+    // like the ts3 client the request goes through and the server refuses a taken
+    // nickname later, the name just stays the same
+    fn rename_self(&self, server_id: ServerId, nick: &str) -> Result<()> {
+        if self.is_nickname_in_use(server_id, nick)? {
+            return Ok(());
+        }
+
+        self.try_with_server(server_id, |server| {
+            let own_client_id = server.own_client_id;
+            server.client(own_client_id)?.name = nick.to_owned();
+            Ok(())
+        })
+    }
+
+    fn move_client(
+        &self,
+        server_id: ServerId,
+        client_id: ConnectionId,
+        channel_id: ChannelId,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let server = state
+            .servers
+            .get_mut(&server_id)
+            .ok_or(anyhow!("server {} not found", server_id.0))?;
+
+        if !server.channels.contains_key(&channel_id) {
+            return Err(anyhow!("channel {} not found", channel_id.0));
+        }
+        server.client(client_id)?.channel_id = channel_id;
+
+        state.moves.push(MockMove {
+            server_id,
+            client_id,
+            channel_id,
+        });
+        Ok(())
+    }
+
+    fn sound_state(&self, server_id: ServerId) -> Result<SoundState> {
+        self.try_with_server(server_id, |server| Ok(server.sound_state))
+    }
+
+    fn set_input_muted(&self, server_id: ServerId, muted: bool) -> Result<()> {
+        self.try_with_server(server_id, |server| {
+            server.sound_state.input_muted = muted;
+            Ok(())
+        })
+    }
+
+    fn set_output_muted(&self, server_id: ServerId, muted: bool) -> Result<()> {
+        self.try_with_server(server_id, |server| {
+            server.sound_state.output_muted = muted;
+            Ok(())
+        })
+    }
+
+    fn is_talking(&self, server_id: ServerId, client_id: ConnectionId) -> Result<bool> {
+        self.try_with_server(server_id, |server| Ok(server.client(client_id)?.talking))
+    }
+
+    fn set_whisper_list(&self, server_id: ServerId, client_ids: &[ConnectionId]) -> Result<()> {
+        self.try_with_server(server_id, |server| {
+            if server.whisper_list_failing {
+                return Err(anyhow!("whisper list rejected"));
+            }
+            server.whisper_list = client_ids.to_vec();
+            Ok(())
+        })
+    }
+
+    fn log(&self, message: &str, _level: LogLevel) {
+        self.state.lock().unwrap().logs.push(message.to_owned());
+    }
+}
+
+impl BackendProvider for MockTeamSpeak {
+    fn with_backend(&self, f: &mut dyn FnMut(&dyn TeamSpeakBackend)) {
+        f(self)
+    }
+}
@@ -1,188 +1,339 @@
+pub mod gate;
+pub mod limits;
 pub mod protocol;
+#[cfg(test)]
+mod test_client;
 
 use anyhow::{anyhow, Ok, Result};
-use simple_websockets::{Event, EventHub, Message, Responder};
 use std::collections::HashMap;
+use std::net::TcpListener;
 
 use std::sync::{Arc, Mutex};
-use ts3plugin::{ClientProperties, ServerId};
+use std::time::Instant;
+use ts3plugin::LogLevel;
 
+use self::gate::{Event, EventHub, Message, Responder, SecurityConfig};
+use self::limits::{Admission, Limits, RateLimiter};
 use self::protocol::{
-    Command, InitiateParameter, ParamMessageType, PlayerStateUpdateParameter, PluginStateParameter,
-    ProtocolMessage, SelfStateUpdateParameter, SoundStateParameter, TalkStateParameter,
+    AuthenticateParameter, Command, Encoding, Error, ErrorParameter, Extension, GameInstanceState,
+    InitiateParameter, InstanceStateParameter, ParamMessageType, PluginStateParameter,
+    ProtocolMessage, ProtocolVersionParameter, SoundStateParameter, SubscribeParameter,
+    SubscriberRole, TalkStateParameter,
 };
 
-const FAKE_SALTY_VERSION: &str = "2.3.6";
-
+// the player states live in the game sessions, where the audio callbacks read them
 struct InstanceState {
     instances: HashMap<String, InitiateParameter>,
-    self_state_by_instance: HashMap<String, SelfStateUpdateParameter>,
-    player_states_by_instance: HashMap<String, Vec<PlayerStateUpdateParameter>>,
+}
+
+// a connected game client
+struct WsClient {
+    responder: Responder,
+    // negotiated RustyChat protocol version, 0 until the client declares one
+    protocol_version: u32,
+    // sent the shared secret, always true when no token is configured
+    authenticated: bool,
+    rate_limiter: RateLimiter,
+    encoding: Encoding,
+    role: SubscriberRole,
+}
+
+// a websocket client receiving the events of an instance
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Subscriber {
+    client_id: u64,
+    role: SubscriberRole,
 }
 
 lazy_static! {
-    static ref CLIENTS: Mutex<HashMap<u64, Responder>> = Mutex::from(HashMap::new());
-    static ref CLIENTS_BY_INSTANCE: Mutex<HashMap<String, u64>> = Mutex::from(HashMap::new());
+    static ref CLIENTS: Mutex<HashMap<u64, WsClient>> = Mutex::from(HashMap::new());
+    static ref CLIENTS_BY_INSTANCE: Mutex<HashMap<String, Vec<Subscriber>>> =
+        Mutex::from(HashMap::new());
 }
 use crate::game::GameHandler;
+use crate::teamspeak::{BackendProvider, SoundState, Ts3Provider};
 
 pub fn start_listen(game_ref: Arc<Mutex<GameHandler>>) {
-    let event_hub = simple_websockets::launch(9151).expect("failed to listen on port 9151");
+    let config = Arc::new(SecurityConfig::from_env());
+    let limits = Limits::from_env();
+    let listener = TcpListener::bind("127.0.0.1:9151").expect("failed to listen on port 9151");
+    let event_hub = gate::launch(listener, config.clone(), limits, Arc::new(Ts3Provider));
 
     std::thread::spawn(move || {
-        let _res = websocket_loop(&event_hub, game_ref);
+        let _res = websocket_loop(&event_hub, game_ref, &Ts3Provider, &config, &limits);
     });
 }
 
-fn websocket_loop(event_hub: &EventHub, game_ref: Arc<Mutex<GameHandler>>) -> Result<()> {
+fn websocket_loop(
+    event_hub: &EventHub,
+    game_ref: Arc<Mutex<GameHandler>>,
+    ts: &dyn BackendProvider,
+    config: &SecurityConfig,
+    limits: &Limits,
+) -> Result<()> {
     let mut instance_state = InstanceState {
         instances: HashMap::new(),
-        self_state_by_instance: HashMap::new(),
-        player_states_by_instance: HashMap::new(),
     };
     loop {
         match event_hub.poll_event() {
             Event::Connect(client_id, responder) => {
-                game_ref.lock().unwrap().ws_connected();
+                ts.with_backend(&mut |ts| game_ref.lock().unwrap().ws_connected(ts));
+                let active_instances = game_ref.lock().unwrap().active_instances();
                 println!("A client connected with id #{}", client_id);
-                handle_connect(&responder);
-                CLIENTS.lock().unwrap().insert(client_id, responder);
+                handle_connect(&responder, active_instances);
+                CLIENTS.lock().unwrap().insert(
+                    client_id,
+                    WsClient {
+                        responder,
+                        protocol_version: 0,
+                        authenticated: config.token.is_none(),
+                        rate_limiter: RateLimiter::new(
+                            limits.max_messages_per_second,
+                            Instant::now(),
+                        ),
+                        encoding: Encoding::Json,
+                        role: SubscriberRole::Game,
+                    },
+                );
             }
             Event::Disconnect(client_id) => {
                 println!("Client #{} disconnected.", client_id);
-                // remove the disconnected client from the clients map:
-                CLIENTS.lock().unwrap().remove(&client_id);
+                handle_disconnect(client_id, &mut instance_state, &game_ref, ts);
             }
             Event::Message(client_id, message) => {
-                let clients_locked = CLIENTS.lock().unwrap();
+                if !admit_message(client_id, limits) {
+                    continue;
+                }
+
                 println!(
                     "Received a message from client #{}: {:?}",
                     client_id, message
                 );
 
-                match message {
-                    Message::Text(text) => {
-                        let parsed_message: serde_json::Result<protocol::ProtocolMessage> =
-                            serde_json::from_str(&text.to_owned());
-
-                        match parsed_message {
-                            std::result::Result::Ok(parsed_message) => match parsed_message.command
-                            {
-                                Command::Initiate => {
-                                    CLIENTS_BY_INSTANCE.lock().unwrap().insert(
-                                        parsed_message.server_unique_identifier.clone().unwrap(),
-                                        client_id,
-                                    );
-                                    handle_init(
-                                        parsed_message.parameter.unwrap(),
-                                        &mut instance_state.instances,
-                                    );
-                                }
-                                Command::Ping => {
-                                    handle_ping(
-                                        parsed_message,
-                                        CLIENTS.lock().unwrap().get(&client_id).unwrap(),
-                                    );
-                                }
-                                Command::SelfStateUpdate => {
-                                    handle_self_state_update(
-                                        parsed_message.parameter.unwrap(),
-                                        &parsed_message.server_unique_identifier.unwrap(),
-                                        &mut instance_state.self_state_by_instance,
-                                    );
-                                }
-                                Command::PlayerStateUpdate => {
-                                    handle_player_state_update(
-                                        parsed_message.parameter.unwrap(),
-                                        &parsed_message.server_unique_identifier.unwrap(),
-                                        &mut instance_state.player_states_by_instance,
-                                    );
-                                }
-                                Command::BulkUpdate => {
-                                    handle_bulk_update(
-                                        parsed_message.parameter.unwrap(),
-                                        &parsed_message.server_unique_identifier.unwrap(),
-                                        &mut instance_state,
-                                    );
-                                }
-                                Command::RemovePlayer => {
-                                    handle_remove_player(
-                                        parsed_message.parameter.unwrap(),
-                                        &parsed_message.server_unique_identifier.unwrap(),
-                                        &mut instance_state,
-                                    );
-                                }
-                                Command::PlaySound => {
-                                    handle_sound_play(parsed_message.parameter.unwrap());
-                                }
-                                Command::StopSound => {
-                                    handle_sound_stop(parsed_message.parameter.unwrap());
-                                }
-                                Command::PhoneCommunicationUpdate => {
-                                    handle_phone_communication_update(
-                                        parsed_message.parameter.unwrap(),
-                                    );
-                                }
-                                Command::StopPhoneCommunication => {
-                                    handle_phone_call_end(parsed_message.parameter.unwrap());
-                                }
-                                Command::RadioCommunicationUpdate => {
-                                    handle_radio_communication_update(
-                                        parsed_message.parameter.unwrap(),
-                                    );
-                                }
-                                Command::StopRadioCommunication => {
-                                    handle_radio_stop(parsed_message.parameter.unwrap());
-                                }
-                                Command::RadioTowerUpdate => {
-                                    handle_radio_tower_update(parsed_message.parameter.unwrap());
-                                }
-                                Command::AddRadioChannelMember => {
-                                    handle_radio_channel_add(parsed_message.parameter.unwrap());
-                                }
-                                Command::UpdateRadioChannelMembers => {
-                                    handle_radio_channel_update(parsed_message.parameter.unwrap());
-                                }
-                                Command::RemoveRadioChannelMember => {
-                                    handle_radio_channel_remove(parsed_message.parameter.unwrap());
-                                }
-                                Command::MegaphoneCommunicationUpdate => {
-                                    handle_megaphone_update(parsed_message.parameter.unwrap());
-                                }
-                                Command::StopMegaphoneCommunication => {
-                                    handle_megaphone_stop(parsed_message.parameter.unwrap());
-                                }
-                                _ => {}
-                            },
-                            Err(err) => {
-                                println!("Error parsing message: {:?}", err);
-                            }
-                        }
+                match decode_message(&message) {
+                    std::result::Result::Ok(message) => handle_message(
+                        client_id,
+                        message,
+                        &mut instance_state,
+                        &game_ref,
+                        ts,
+                        config,
+                        limits,
+                    ),
+                    Err(err) => {
+                        println!("Error parsing message: {:?}", err);
                     }
-                    Message::Binary(bin) => {
-                        println!("Received binary message: {:?}", bin);
+                }
+            }
+        }
+    }
+}
+
+// checks who sent the command before anything in it is trusted
+fn handle_message(
+    client_id: u64,
+    mut parsed_message: ProtocolMessage,
+    instance_state: &mut InstanceState,
+    game_ref: &Arc<Mutex<GameHandler>>,
+    ts: &dyn BackendProvider,
+    config: &SecurityConfig,
+    limits: &Limits,
+) {
+    let command = parsed_message.command;
+    if !may_send(client_id, command) {
+        println!("Client #{} may not send {:?}", client_id, command);
+        return;
+    }
+    if command.requires_authentication() && !is_authenticated(client_id) {
+        let reason = format!("{:?} without a valid token", command);
+        reject_client(client_id, &reason, false, ts);
+        return;
+    }
+
+    if !command.drives_instance() {
+        match command {
+            Command::Ping => {
+                handle_ping(client_id, parsed_message);
+            }
+            Command::Authenticate => {
+                if let Some(parameter) = take_parameter(client_id, &mut parsed_message) {
+                    handle_authenticate(client_id, parameter, config, ts);
+                }
+            }
+            Command::Initiate => {
+                if let Some(parameter) = take_parameter(client_id, &mut parsed_message) {
+                    handle_init(
+                        client_id,
+                        parameter,
+                        &mut instance_state.instances,
+                        game_ref,
+                        ts,
+                    );
+                }
+            }
+            Command::Subscribe => match parsed_message.server_unique_identifier.clone() {
+                Some(server_id) => {
+                    if let Some(parameter) = take_parameter(client_id, &mut parsed_message) {
+                        handle_subscribe(client_id, parameter, &server_id);
                     }
                 }
+                None => {
+                    send_error_to_client(
+                        client_id,
+                        None,
+                        Error::InvalidValue,
+                        "Subscribe without a server unique identifier",
+                    );
+                }
+            },
+            Command::ProtocolVersion => {
+                if let Some(parameter) = take_parameter(client_id, &mut parsed_message) {
+                    handle_protocol_version(client_id, parameter);
+                }
+            }
+            _ => {}
+        }
+        return;
+    }
+
+    // only the game that sent Initiate for an instance may drive it
+    let server_id = match &parsed_message.server_unique_identifier {
+        Some(server_id) if is_game_of(client_id, server_id) => server_id.to_owned(),
+        server_id => {
+            send_error_to_client(
+                client_id,
+                server_id.as_ref(),
+                Error::Unauthorized,
+                &format!(
+                    "{:?} for an instance this connection did not initiate",
+                    command
+                ),
+            );
+            return;
+        }
+    };
 
-                // retrieve this client's `Responder`:
-                let responder = clients_locked
-                    .get(&client_id)
-                    .ok_or(anyhow!("Client responder not found"))?;
-                // echo the message back:
-                responder.send(Message::Text("Hello World".to_owned()));
+    match command {
+        Command::Reset => {
+            handle_reset(&server_id, instance_state, game_ref, ts);
+        }
+        Command::SelfStateUpdate => {
+            if let Some(parameter) = take_parameter(client_id, &mut parsed_message) {
+                handle_self_state_update(parameter, &server_id, game_ref, ts);
+            }
+        }
+        Command::PlayerStateUpdate => {
+            if let Some(parameter) = take_parameter(client_id, &mut parsed_message) {
+                handle_player_state_update(parameter, &server_id, game_ref, ts);
+            }
+        }
+        Command::BulkUpdate => {
+            if let Some(parameter) = take_parameter(client_id, &mut parsed_message) {
+                handle_bulk_update(client_id, parameter, &server_id, game_ref, ts, limits);
+            }
+        }
+        Command::PlayerStateDelta
+            if !supports_extension(client_id, Extension::PlayerStateDeltas) =>
+        {
+            println!("Client #{} sent deltas without negotiating them", client_id);
+        }
+        Command::PlayerStateDelta => {
+            if let Some(parameter) = take_parameter(client_id, &mut parsed_message) {
+                handle_player_state_delta(client_id, parameter, &server_id, game_ref, ts, limits);
+            }
+        }
+        Command::RemovePlayer => {
+            if let Some(parameter) = take_parameter(client_id, &mut parsed_message) {
+                handle_remove_player(parameter, &server_id, game_ref, ts);
+            }
+        }
+        Command::PlaySound => {
+            if let Some(parameter) = take_parameter(client_id, &mut parsed_message) {
+                handle_sound_play(parameter);
+            }
+        }
+        Command::StopSound => {
+            if let Some(parameter) = take_parameter(client_id, &mut parsed_message) {
+                handle_sound_stop(parameter);
+            }
+        }
+        Command::PhoneCommunicationUpdate => {
+            if let Some(parameter) = take_parameter(client_id, &mut parsed_message) {
+                handle_phone_communication_update(parameter, &server_id, game_ref, ts);
+            }
+        }
+        Command::StopPhoneCommunication => {
+            if let Some(parameter) = take_parameter(client_id, &mut parsed_message) {
+                handle_phone_call_end(parameter, &server_id, game_ref, ts);
+            }
+        }
+        Command::RadioCommunicationUpdate => {
+            if let Some(parameter) = take_parameter(client_id, &mut parsed_message) {
+                handle_radio_communication_update(parameter, &server_id, game_ref, ts);
+            }
+        }
+        Command::StopRadioCommunication => {
+            if let Some(parameter) = take_parameter(client_id, &mut parsed_message) {
+                handle_radio_stop(parameter, &server_id, game_ref, ts);
+            }
+        }
+        Command::RadioTowerUpdate => {
+            if let Some(parameter) = take_parameter(client_id, &mut parsed_message) {
+                handle_radio_tower_update(parameter);
+            }
+        }
+        Command::AddRadioChannelMember => {
+            if let Some(parameter) = take_parameter(client_id, &mut parsed_message) {
+                handle_radio_channel_add(parameter, &server_id, game_ref, ts);
+            }
+        }
+        Command::UpdateRadioChannelMembers => {
+            if let Some(parameter) = take_parameter(client_id, &mut parsed_message) {
+                handle_radio_channel_update(parameter, &server_id, game_ref, ts);
             }
         }
+        Command::RemoveRadioChannelMember => {
+            if let Some(parameter) = take_parameter(client_id, &mut parsed_message) {
+                handle_radio_channel_remove(parameter, &server_id, game_ref, ts);
+            }
+        }
+        Command::MegaphoneCommunicationUpdate => {
+            if let Some(parameter) = take_parameter(client_id, &mut parsed_message) {
+                handle_megaphone_update(parameter);
+            }
+        }
+        Command::StopMegaphoneCommunication => {
+            if let Some(parameter) = take_parameter(client_id, &mut parsed_message) {
+                handle_megaphone_stop(parameter);
+            }
+        }
+        _ => {}
     }
 }
 
-fn handle_connect(responder: &Responder) {
+// a missing parameter is answered with an error, it must never take down the ts client
+fn take_parameter(client_id: u64, message: &mut ProtocolMessage) -> Option<ParamMessageType> {
+    let parameter = message.parameter.take();
+    if parameter.is_none() {
+        send_error_to_client(
+            client_id,
+            message.server_unique_identifier.as_ref(),
+            Error::InvalidValue,
+            &format!("{:?} without a parameter", message.command),
+        );
+    }
+    parameter
+}
+
+fn handle_connect(responder: &Responder, active_instances: u32) {
     let message = protocol::ProtocolMessage {
         command: Command::PluginState,
         server_unique_identifier: None,
         parameter: Some(ParamMessageType::PluginStateParameter(
             PluginStateParameter {
-                version: FAKE_SALTY_VERSION.to_owned(),
-                active_instances: 1,
+                version: protocol::SALTY_CHAT_COMPAT_VERSION.to_owned(),
+                active_instances,
+                rusty_chat_version: protocol::RUSTY_CHAT_PROTOCOL_VERSION,
             },
         )),
     };
@@ -190,12 +341,234 @@ fn handle_connect(responder: &Responder) {
     responder.send(Message::Text(serde_json::to_string(&message).unwrap()));
 }
 
-fn handle_init(message: ParamMessageType, instance_state: &mut HashMap<String, InitiateParameter>) {
-    if let ParamMessageType::InitiateParameter(initiate_parameter) = message {
+// the version declared by the client, answered with the version both sides use
+fn handle_protocol_version(client_id: u64, message: ParamMessageType) {
+    if let ParamMessageType::ProtocolVersionParameter(protocol_version) = message {
+        let mut clients = CLIENTS.lock().unwrap();
+        let client = match clients.get_mut(&client_id) {
+            Some(client) => client,
+            None => return,
+        };
+        client.protocol_version = protocol::negotiate_version(protocol_version.version);
+        let encoding = match protocol_version.encoding {
+            Encoding::MessagePack
+                if Extension::MessagePack.is_supported(client.protocol_version) =>
+            {
+                Encoding::MessagePack
+            }
+            _ => Encoding::Json,
+        };
+
+        // the reply is still json, the agreed encoding applies from the next message on
+        let message = ProtocolMessage {
+            command: Command::ProtocolVersion,
+            server_unique_identifier: None,
+            parameter: Some(ParamMessageType::ProtocolVersionParameter(
+                ProtocolVersionParameter {
+                    version: client.protocol_version,
+                    encoding,
+                },
+            )),
+        };
+        client
+            .responder
+            .send(Message::Text(serde_json::to_string(&message).unwrap()));
+        client.encoding = encoding;
+    }
+}
+
+fn handle_authenticate(
+    client_id: u64,
+    message: ParamMessageType,
+    config: &SecurityConfig,
+    ts: &dyn BackendProvider,
+) {
+    if let ParamMessageType::AuthenticateParameter(AuthenticateParameter { token }) = message {
+        if !config.is_token_valid(&token) {
+            reject_client(client_id, "invalid token", true, ts);
+            return;
+        }
+
+        if let Some(client) = CLIENTS.lock().unwrap().get_mut(&client_id) {
+            client.authenticated = true;
+        }
+    }
+}
+
+fn is_authenticated(client_id: u64) -> bool {
+    CLIENTS
+        .lock()
+        .unwrap()
+        .get(&client_id)
+        .map(|client| client.authenticated)
+        .unwrap_or(false)
+}
+
+fn is_game_of(client_id: u64, server_id: &str) -> bool {
+    CLIENTS_BY_INSTANCE
+        .lock()
+        .unwrap()
+        .get(server_id)
+        .map(|subscribers| {
+            subscribers.iter().any(|subscriber| {
+                subscriber.client_id == client_id && subscriber.role == SubscriberRole::Game
+            })
+        })
+        .unwrap_or(false)
+}
+
+fn may_send(client_id: u64, command: Command) -> bool {
+    CLIENTS
+        .lock()
+        .unwrap()
+        .get(&client_id)
+        .map(|client| client.role.may_send(command))
+        .unwrap_or(false)
+}
+
+fn supports_extension(client_id: u64, extension: Extension) -> bool {
+    CLIENTS
+        .lock()
+        .unwrap()
+        .get(&client_id)
+        .map(|client| extension.is_supported(client.protocol_version))
+        .unwrap_or(false)
+}
+
+// logs the rejection in the ts client and tells the game scripts why
+fn reject_client(client_id: u64, reason: &str, close: bool, ts: &dyn BackendProvider) {
+    let log_message = format!("Rejected websocket client #{}: {}", client_id, reason);
+    ts.with_backend(&mut |ts| ts.log(&log_message, LogLevel::Warning));
+
+    send_error_to_client(client_id, None, Error::Unauthorized, reason);
+    if close {
+        if let Some(client) = CLIENTS.lock().unwrap().get(&client_id) {
+            client.responder.close();
+        }
+    }
+}
+
+// drops everything beyond the client's rate limit, oversized messages never get
+// here because the gate refuses them
+fn admit_message(client_id: u64, limits: &Limits) -> bool {
+    let now = Instant::now();
+    let admission = match CLIENTS.lock().unwrap().get_mut(&client_id) {
+        Some(client) => client.rate_limiter.admit(now),
+        None => return false,
+    };
+
+    match admission {
+        Admission::Allowed => true,
+        Admission::Dropped { report } => {
+            if report {
+                println!("Client #{} exceeded the message rate limit", client_id);
+                send_error_to_client(
+                    client_id,
+                    None,
+                    Error::TooManyMessages,
+                    &format!(
+                        "more than {} messages per second, dropping messages",
+                        limits.max_messages_per_second
+                    ),
+                );
+            }
+            false
+        }
+    }
+}
+
+fn send_error_to_client(client_id: u64, server_id: Option<&String>, error: Error, message: &str) {
+    let message = ProtocolMessage {
+        command: Command::Error,
+        server_unique_identifier: server_id.cloned(),
+        parameter: Some(ParamMessageType::ErrorParameter(ErrorParameter {
+            error,
+            message: message.to_owned(),
+        })),
+    };
+
+    if let Some(client) = CLIENTS.lock().unwrap().get(&client_id) {
+        let _ = send_to_client(client, &message);
+    }
+}
+
+// observers only listen, the connection can't drive an instance afterwards
+fn handle_subscribe(client_id: u64, message: ParamMessageType, server_id: &String) {
+    if let ParamMessageType::SubscribeParameter(SubscribeParameter { role }) = message {
+        let error = if !is_authenticated(client_id) {
+            Some((Error::Unauthorized, "Subscribe without a valid token"))
+        } else if !supports_extension(client_id, Extension::Subscribers) {
+            Some((Error::InvalidValue, "subscribers are not negotiated"))
+        } else if role != SubscriberRole::Observer {
+            Some((Error::InvalidValue, "only observers can subscribe"))
+        } else {
+            None
+        };
+
+        if let Some((error, reason)) = error {
+            send_error_to_client(client_id, Some(server_id), error, reason);
+            return;
+        }
+
+        if let Some(client) = CLIENTS.lock().unwrap().get_mut(&client_id) {
+            client.role = role;
+        }
+        subscribe(server_id, client_id, role);
+    }
+}
+
+// an instance has at most one game, a new Initiate takes over from the previous one
+fn subscribe(server_id: &String, client_id: u64, role: SubscriberRole) {
+    let mut clients_by_instance = CLIENTS_BY_INSTANCE.lock().unwrap();
+    let subscribers = clients_by_instance.entry(server_id.to_owned()).or_default();
+
+    subscribers.retain(|subscriber| {
+        subscriber.client_id != client_id
+            && !(role == SubscriberRole::Game && subscriber.role == SubscriberRole::Game)
+    });
+    subscribers.push(Subscriber { client_id, role });
+}
+
+fn handle_init(
+    client_id: u64,
+    message: ParamMessageType,
+    instance_state: &mut HashMap<String, InitiateParameter>,
+    game_ref: &Arc<Mutex<GameHandler>>,
+    ts: &dyn BackendProvider,
+) {
+    if let ParamMessageType::InitiateParameter(mut initiate_parameter) = message {
+        let protocol_version = match CLIENTS.lock().unwrap().get_mut(&client_id) {
+            Some(client) => {
+                if initiate_parameter.rusty_chat_version != 0 {
+                    client.protocol_version =
+                        protocol::negotiate_version(initiate_parameter.rusty_chat_version);
+                }
+                client.protocol_version
+            }
+            None => 0,
+        };
+        if !Extension::NameFallback.is_supported(protocol_version) {
+            initiate_parameter.name_fallback_attempts = 0;
+        }
+
+        subscribe(
+            &initiate_parameter.server_unique_identifier,
+            client_id,
+            SubscriberRole::Game,
+        );
+
         if instance_state.contains_key(&initiate_parameter.server_unique_identifier) {
             instance_state.remove(&initiate_parameter.server_unique_identifier);
         }
 
+        // the ts api is locked before the game handler, same as in the plugin callbacks
+        ts.with_backend(&mut |ts| {
+            game_ref
+                .lock()
+                .unwrap()
+                .initiate(ts, initiate_parameter.clone())
+        });
+
         instance_state.insert(
             initiate_parameter.server_unique_identifier.to_owned(),
             initiate_parameter,
@@ -203,117 +576,252 @@ fn handle_init(message: ParamMessageType, instance_state: &mut HashMap<String, I
     }
 }
 
-fn handle_ping(message: ProtocolMessage, responder: &Responder) {
+fn handle_reset(
+    server_id: &String,
+    instance_state: &mut InstanceState,
+    game_ref: &Arc<Mutex<GameHandler>>,
+    ts: &dyn BackendProvider,
+) {
+    instance_state.instances.remove(server_id);
+
+    ts.with_backend(&mut |ts| game_ref.lock().unwrap().reset(ts, server_id));
+}
+
+// a vanished game gets the same cleanup as if it had sent Reset for each of its instances
+fn handle_disconnect(
+    client_id: u64,
+    instance_state: &mut InstanceState,
+    game_ref: &Arc<Mutex<GameHandler>>,
+    ts: &dyn BackendProvider,
+) {
+    CLIENTS.lock().unwrap().remove(&client_id);
+
+    // only instances this client was the game of are reset, observers just leave
+    let mut server_uids = Vec::new();
+    CLIENTS_BY_INSTANCE
+        .lock()
+        .unwrap()
+        .retain(|server_uid, subscribers| {
+            subscribers.retain(|subscriber| {
+                if subscriber.client_id != client_id {
+                    return true;
+                }
+                if subscriber.role == SubscriberRole::Game {
+                    server_uids.push(server_uid.to_owned());
+                }
+                false
+            });
+            !subscribers.is_empty()
+        });
+
+    for server_uid in server_uids {
+        handle_reset(&server_uid, instance_state, game_ref, ts);
+    }
+}
+
+fn handle_ping(client_id: u64, message: ProtocolMessage) {
     let message = protocol::ProtocolMessage {
         command: Command::Pong,
         server_unique_identifier: message.server_unique_identifier,
         parameter: None,
     };
 
-    responder.send(Message::Text(serde_json::to_string(&message).unwrap()));
+    if let Some(client) = CLIENTS.lock().unwrap().get(&client_id) {
+        let _ = send_to_client(client, &message);
+    }
 }
 
 pub fn handle_self_state_update(
     message: ParamMessageType,
-    server_id: &String,
-    self_state_by_instance: &mut HashMap<String, SelfStateUpdateParameter>,
+    server_id: &str,
+    game_ref: &Arc<Mutex<GameHandler>>,
+    ts: &dyn BackendProvider,
 ) {
     if let ParamMessageType::SelfStateUpdateParameter(self_state_update_parameter) = message {
-        self_state_by_instance.insert(server_id.to_owned(), self_state_update_parameter);
+        let mut self_state = Some(self_state_update_parameter);
+        ts.with_backend(&mut |ts| {
+            if let Some(self_state) = self_state.take() {
+                game_ref
+                    .lock()
+                    .unwrap()
+                    .self_state_update(ts, server_id, self_state)
+            }
+        });
     }
 }
 
 pub fn handle_player_state_update(
     message: ParamMessageType,
-    server_id: &String,
-    player_states_by_instance: &mut HashMap<String, Vec<PlayerStateUpdateParameter>>,
+    server_id: &str,
+    game_ref: &Arc<Mutex<GameHandler>>,
+    ts: &dyn BackendProvider,
 ) {
     if let ParamMessageType::PlayerStateUpdateParameter(player_state_update_parameter) = message {
-        let players = player_states_by_instance.get(server_id).unwrap();
-        let mut new_players: Vec<PlayerStateUpdateParameter> = players.to_vec();
-
-        let player = players
-            .iter()
-            .find(|player| player.name == player_state_update_parameter.name);
-
-        match player {
-            Some(player) => {
-                let index = new_players
-                    .iter()
-                    .position(|x| x.name == player.name)
-                    .unwrap();
-                new_players.remove(index);
-                new_players.push(player_state_update_parameter);
-            }
-            None => {
-                new_players.push(player_state_update_parameter);
+        let mut player = Some(player_state_update_parameter);
+        ts.with_backend(&mut |ts| {
+            if let Some(player) = player.take() {
+                game_ref
+                    .lock()
+                    .unwrap()
+                    .player_state_update(ts, server_id, player)
             }
+        });
+    }
+}
+
+pub fn handle_player_state_delta(
+    client_id: u64,
+    message: ParamMessageType,
+    server_id: &String,
+    game_ref: &Arc<Mutex<GameHandler>>,
+    ts: &dyn BackendProvider,
+    limits: &Limits,
+) {
+    if let ParamMessageType::PlayerStateDeltaParameter(delta_message) = message {
+        if delta_message.player_states.len() > limits.max_bulk_players {
+            send_error_to_client(
+                client_id,
+                Some(server_id),
+                Error::TooManyPlayers,
+                &format!(
+                    "delta update with {} players exceeds the limit of {}",
+                    delta_message.player_states.len(),
+                    limits.max_bulk_players
+                ),
+            );
+            return;
         }
 
-        player_states_by_instance.insert(server_id.to_owned(), new_players);
+        let mut deltas = Some(delta_message.player_states);
+        ts.with_backend(&mut |ts| {
+            if let Some(deltas) = deltas.take() {
+                game_ref
+                    .lock()
+                    .unwrap()
+                    .player_state_deltas(ts, server_id, deltas)
+            }
+        });
     }
 }
 
 fn handle_bulk_update(
+    client_id: u64,
     message: ParamMessageType,
     server_id: &String,
-    instance_state: &mut InstanceState,
+    game_ref: &Arc<Mutex<GameHandler>>,
+    ts: &dyn BackendProvider,
+    limits: &Limits,
 ) {
     if let ParamMessageType::BulkUpdateParameter(bulk_message) = message {
-        instance_state
-            .self_state_by_instance
-            .insert(server_id.to_owned(), bulk_message.self_state);
+        if bulk_message.player_states.len() > limits.max_bulk_players {
+            send_error_to_client(
+                client_id,
+                Some(server_id),
+                Error::TooManyPlayers,
+                &format!(
+                    "bulk update with {} players exceeds the limit of {}",
+                    bulk_message.player_states.len(),
+                    limits.max_bulk_players
+                ),
+            );
+            return;
+        }
 
-        instance_state
-            .player_states_by_instance
-            .insert(server_id.to_owned(), bulk_message.player_states.to_owned());
+        let mut bulk_message = Some(bulk_message);
+        ts.with_backend(&mut |ts| {
+            if let Some(bulk_message) = bulk_message.take() {
+                game_ref.lock().unwrap().bulk_update(
+                    ts,
+                    server_id,
+                    bulk_message.self_state,
+                    bulk_message.player_states,
+                )
+            }
+        });
     }
 }
 
 fn handle_remove_player(
     message: ParamMessageType,
-    server_id: &String,
-    instance_state: &mut InstanceState,
+    server_id: &str,
+    game_ref: &Arc<Mutex<GameHandler>>,
+    ts: &dyn BackendProvider,
 ) {
     if let ParamMessageType::RemovePlayerParameter(remove_player_param) = message {
-        let players_cloned = instance_state
-            .player_states_by_instance
-            .get(server_id)
-            .unwrap()
-            .iter()
-            .filter(|player| player.name != remove_player_param.name)
-            .cloned()
-            .collect::<Vec<PlayerStateUpdateParameter>>();
-
-        instance_state
-            .player_states_by_instance
-            .insert(server_id.to_owned(), players_cloned.to_owned());
+        ts.with_backend(&mut |ts| {
+            game_ref
+                .lock()
+                .unwrap()
+                .remove_player(ts, server_id, &remove_player_param.name)
+        });
     }
 }
 
-fn handle_phone_communication_update(message: ParamMessageType) {
-    if let ParamMessageType::PhoneCommunicationUpdateParameter(_phone_communication_update) =
-        message
+fn handle_phone_communication_update(
+    message: ParamMessageType,
+    server_id: &str,
+    game_ref: &Arc<Mutex<GameHandler>>,
+    ts: &dyn BackendProvider,
+) {
+    if let ParamMessageType::PhoneCommunicationUpdateParameter(phone_communication_update) = message
     {
-        // Handle phone communication update
+        ts.with_backend(&mut |ts| {
+            game_ref.lock().unwrap().phone_communication_update(
+                ts,
+                server_id,
+                &phone_communication_update.name,
+            )
+        });
     }
 }
 
-fn handle_phone_call_end(message: ParamMessageType) {
-    if let ParamMessageType::StopPhoneCommunicationParameter(_phone_call_end) = message {
-        // Handle phone communication end
+fn handle_phone_call_end(
+    message: ParamMessageType,
+    server_id: &str,
+    game_ref: &Arc<Mutex<GameHandler>>,
+    ts: &dyn BackendProvider,
+) {
+    if let ParamMessageType::StopPhoneCommunicationParameter(phone_call_end) = message {
+        ts.with_backend(&mut |ts| {
+            game_ref
+                .lock()
+                .unwrap()
+                .stop_phone_communication(ts, server_id, &phone_call_end.name)
+        });
     }
 }
 
-fn handle_radio_communication_update(message: ParamMessageType) {
-    if let ParamMessageType::RadioCommunicationUpdateParameter(_radio_update_param) = message {
-        // Handle phone communication update
+fn handle_radio_communication_update(
+    message: ParamMessageType,
+    server_id: &str,
+    game_ref: &Arc<Mutex<GameHandler>>,
+    ts: &dyn BackendProvider,
+) {
+    if let ParamMessageType::RadioCommunicationUpdateParameter(radio_update_param) = message {
+        ts.with_backend(&mut |ts| {
+            game_ref.lock().unwrap().radio_communication_update(
+                ts,
+                server_id,
+                &radio_update_param.name,
+                radio_update_param.secondary,
+            )
+        });
     }
 }
 
-fn handle_radio_stop(message: ParamMessageType) {
-    if let ParamMessageType::StopRadioCommunicationParameter(_radio_call_end) = message {
-        // Handle phone communication end
+fn handle_radio_stop(
+    message: ParamMessageType,
+    server_id: &str,
+    game_ref: &Arc<Mutex<GameHandler>>,
+    ts: &dyn BackendProvider,
+) {
+    if let ParamMessageType::StopRadioCommunicationParameter(radio_call_end) = message {
+        ts.with_backend(&mut |ts| {
+            game_ref
+                .lock()
+                .unwrap()
+                .stop_radio_communication(ts, server_id, &radio_call_end.name)
+        });
     }
 }
 
@@ -323,23 +831,59 @@ fn handle_radio_tower_update(message: ParamMessageType) {
     }
 }
 
-fn handle_radio_channel_add(message: ParamMessageType) {
-    if let ParamMessageType::AddRadioChannelMemberParameter(_radio_cannel_member_add) = message {
-        // Handle add radio channel member
+fn handle_radio_channel_add(
+    message: ParamMessageType,
+    server_id: &str,
+    game_ref: &Arc<Mutex<GameHandler>>,
+    ts: &dyn BackendProvider,
+) {
+    if let ParamMessageType::AddRadioChannelMemberParameter(radio_channel_member_add) = message {
+        ts.with_backend(&mut |ts| {
+            game_ref.lock().unwrap().add_radio_channel_member(
+                ts,
+                server_id,
+                &radio_channel_member_add.player_name,
+                radio_channel_member_add.is_primary_channel,
+            )
+        });
     }
 }
 
-fn handle_radio_channel_update(message: ParamMessageType) {
-    if let ParamMessageType::UpdateRadioChannelMembersParameter(_radio_channel_update) = message {
-        // Handle add radio channel update
+fn handle_radio_channel_update(
+    message: ParamMessageType,
+    server_id: &str,
+    game_ref: &Arc<Mutex<GameHandler>>,
+    ts: &dyn BackendProvider,
+) {
+    if let ParamMessageType::UpdateRadioChannelMembersParameter(radio_channel_update) = message {
+        ts.with_backend(&mut |ts| {
+            game_ref.lock().unwrap().update_radio_channel_members(
+                ts,
+                server_id,
+                radio_channel_update.player_names.clone(),
+                radio_channel_update.is_primary_channel,
+            )
+        });
     }
 }
 
-fn handle_radio_channel_remove(message: ParamMessageType) {
-    if let ParamMessageType::RemoveRadioChannelMemberParameter(_radio_cannel_member_remove) =
+fn handle_radio_channel_remove(
+    message: ParamMessageType,
+    server_id: &str,
+    game_ref: &Arc<Mutex<GameHandler>>,
+    ts: &dyn BackendProvider,
+) {
+    if let ParamMessageType::RemoveRadioChannelMemberParameter(radio_channel_member_remove) =
         message
     {
-        // Handle remove radio channel member
+        ts.with_backend(&mut |ts| {
+            game_ref.lock().unwrap().remove_radio_channel_member(
+                ts,
+                server_id,
+                &radio_channel_member_remove.player_name,
+                radio_channel_member_remove.is_primary_channel,
+            )
+        });
     }
 }
 
@@ -350,7 +894,7 @@ fn handle_megaphone_update(message: ParamMessageType) {
 }
 
 fn handle_megaphone_stop(message: ParamMessageType) {
-    if let ParamMessageType::MegaphoneCommunicationUpdateParameter(_megaphone_stop) = message {
+    if let ParamMessageType::StopMegaphoneCommunicationParameter(_megaphone_stop) = message {
         // Handle megaphone stop
     }
 }
@@ -371,21 +915,31 @@ fn handle_sound_stop(message: ParamMessageType) {
 // 1. SoundState (on mic and speaker toggle) X
 // 2. TalkState (on start and on stop talking)
 // 3. RadioTrafficState (Sent by the plugin when radio traffic is received, breaks up or ends.)
+// 4. InstanceState (on entering or leaving a swiss channel)
+// 5. Error (RustyChat extension, e.g. when the requested name is not available)
 
-pub fn on_sound_state_toggle(
-    server_id: &String,
-    is_microphone_enabled: bool,
-    is_microphone_muted: bool,
-    is_sound_enabled: bool,
-    is_sound_muted: bool,
-) -> Result<()> {
-    let clients_by_instance_locked = CLIENTS_BY_INSTANCE.lock().unwrap();
+pub fn on_instance_state_change(server_id: &String, state: GameInstanceState) -> Result<()> {
+    let instance_state_message = ParamMessageType::InstanceStateParameter(InstanceStateParameter {
+        is_connected_to_server: true,
+        is_ready: true,
+        state,
+    });
+
+    let message = ProtocolMessage {
+        command: Command::InstanceState,
+        server_unique_identifier: Some(server_id.to_owned()),
+        parameter: Some(instance_state_message),
+    };
+
+    send_to_instance(server_id, &message)
+}
 
+pub fn on_sound_state_toggle(server_id: &String, sound_state: SoundState) -> Result<()> {
     let sound_state_message = ParamMessageType::SoundStateParameter(SoundStateParameter {
-        is_microphone_enabled,
-        is_microphone_muted,
-        is_sound_enabled,
-        is_sound_muted,
+        is_microphone_enabled: sound_state.input_hardware_enabled,
+        is_microphone_muted: sound_state.input_muted,
+        is_sound_enabled: sound_state.output_hardware_enabled,
+        is_sound_muted: sound_state.output_muted,
     });
 
     let message = ProtocolMessage {
@@ -394,28 +948,10 @@ pub fn on_sound_state_toggle(
         parameter: Some(sound_state_message),
     };
 
-    let message = serde_json::to_string(&message)?;
-
-    let client_id = clients_by_instance_locked.get(server_id).ok_or(anyhow!(
-        "ws client for server {} not found in list",
-        server_id
-    ))?;
-
-    CLIENTS
-        .lock()
-        .unwrap()
-        .get(&client_id)
-        .ok_or(anyhow!(
-            "responder for client {} not found in list",
-            client_id
-        ))?
-        .send(Message::Text(message));
-
-    Ok(())
+    send_to_instance(server_id, &message)
 }
 
 pub fn on_talk_state_toggle(server_id: &String, is_talking: bool, name: &str) -> Result<()> {
-    let clients_by_instance_locked = CLIENTS_BY_INSTANCE.lock().unwrap();
     let talk_state_message = ParamMessageType::TalkStateParameter(TalkStateParameter {
         is_talking,
         name: name.to_owned(),
@@ -427,52 +963,865 @@ pub fn on_talk_state_toggle(server_id: &String, is_talking: bool, name: &str) ->
         parameter: Some(talk_state_message),
     };
 
-    let message = serde_json::to_string(&message)?;
+    send_to_instance(server_id, &message)
+}
 
-    let client_id = clients_by_instance_locked
-        .get(server_id)
-        .ok_or(anyhow!("err"))?;
+pub fn on_error(server_id: &String, error: Error, message: &str) -> Result<()> {
+    let error_message = ParamMessageType::ErrorParameter(ErrorParameter {
+        error,
+        message: message.to_owned(),
+    });
 
-    CLIENTS
-        .lock()
-        .unwrap()
-        .get(&client_id)
-        .ok_or(anyhow!("not connected"))?
-        .send(Message::Text(message));
+    let message = ProtocolMessage {
+        command: Command::Error,
+        server_unique_identifier: Some(server_id.to_owned()),
+        parameter: Some(error_message),
+    };
+
+    send_to_instance(server_id, &message)
+}
+
+// fans the message out to every subscriber of the instance whose role receives it,
+// subscribers that can't be reached anymore are dropped without holding up the others
+fn send_to_instance(server_id: &String, message: &ProtocolMessage) -> Result<()> {
+    let mut clients_by_instance = CLIENTS_BY_INSTANCE.lock().unwrap();
+    let subscribers = clients_by_instance.get_mut(server_id).ok_or(anyhow!(
+        "ws client for server {} not found in list",
+        server_id
+    ))?;
+
+    let clients = CLIENTS.lock().unwrap();
+    subscribers.retain(|subscriber| {
+        if !subscriber.role.receives(message.command) {
+            return true;
+        }
+
+        let delivered = match clients.get(&subscriber.client_id) {
+            Some(client) => match encode_for_client(client, message) {
+                std::result::Result::Ok(Some(encoded)) => client.responder.send(encoded),
+                std::result::Result::Ok(None) => true,
+                Err(err) => {
+                    println!(
+                        "Failed to encode {:?} for client #{}: {:?}",
+                        message.command, subscriber.client_id, err
+                    );
+                    return true;
+                }
+            },
+            None => false,
+        };
+        if !delivered {
+            println!(
+                "Client #{} is gone, removing it from the subscribers of {}",
+                subscriber.client_id, server_id
+            );
+        }
+        delivered
+    });
+    if subscribers.is_empty() {
+        clients_by_instance.remove(server_id);
+    }
 
     Ok(())
 }
 
-pub fn on_self_variable_update(
-    _server_id: ServerId,
-    flag: ClientProperties,
-    _old_value: String,
-    _new_value: String,
-) {
-    match flag {
-        _ => {}
+fn send_to_client(client: &WsClient, message: &ProtocolMessage) -> Result<()> {
+    if let Some(encoded) = encode_for_client(client, message)? {
+        client.responder.send(encoded);
+    }
+
+    Ok(())
+}
+
+// extension commands are silently dropped for clients that did not negotiate them
+fn encode_for_client(client: &WsClient, message: &ProtocolMessage) -> Result<Option<Message>> {
+    if let Some(extension) = message.command.extension() {
+        if !extension.is_supported(client.protocol_version) {
+            return Ok(None);
+        }
     }
+
+    Ok(Some(match client.encoding {
+        Encoding::Json => Message::Text(serde_json::to_string(message)?),
+        Encoding::MessagePack => Message::Binary(rmp_serde::to_vec_named(message)?),
+    }))
+}
+
+fn decode_message(message: &Message) -> Result<ProtocolMessage> {
+    Ok(match message {
+        Message::Text(text) => serde_json::from_str(text)?,
+        Message::Binary(bin) => rmp_serde::from_slice(bin)?,
+    })
 }
 
 #[cfg(test)]
 mod tests {
-    use tungstenite::client::connect;
-    use url::Url;
+    use std::time::{Duration, Instant};
+
+    use super::gate::SecurityConfig;
+    use super::limits::Limits;
+    use super::protocol::Command;
+    use super::protocol::SubscriberRole;
+    use super::test_client::TestServer;
+    use super::{Subscriber, CLIENTS, CLIENTS_BY_INSTANCE};
+    use serde_json::json;
+    use ts3plugin::{ChannelId, ConnectionId};
+
+    use crate::teamspeak::TeamSpeakBackend;
+
+    const SERVER_UID: &str = "fake-server-uid";
+    const LOBBY: ChannelId = ChannelId(1);
+    const GAME_CHANNEL: ChannelId = ChannelId(10);
+    const SUPPORT_CHANNEL: ChannelId = ChannelId(20);
+
+    fn connected_server() -> TestServer {
+        let server = TestServer::start();
+        let server_id = server.ts.connect(SERVER_UID, "TS Nickname", LOBBY);
+        server.ts.add_channel(server_id, GAME_CHANNEL, "Game");
+        server.ts.add_channel(server_id, SUPPORT_CHANNEL, "Support");
+        server
+    }
+
+    fn wait_for(condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !condition() {
+            assert!(Instant::now() < deadline, "condition not met in time");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn own_client(server: &TestServer) -> (ts3plugin::ServerId, ConnectionId) {
+        let server_id = server.ts.server_by_uid(SERVER_UID).unwrap();
+        (server_id, server.ts.own_client_id(server_id).unwrap())
+    }
+
+    #[test]
+    fn test_plugin_state_on_connect() {
+        let server = TestServer::start();
+        let mut client = server.connect();
+
+        let message = client.expect(Command::PluginState);
+
+        assert_eq!(message["Parameter"]["Version"], "2.3.6");
+        assert_eq!(message["Parameter"]["ActiveInstances"], 0);
+        assert_eq!(message["Parameter"]["RustyChatVersion"], 4);
+    }
+
+    #[test]
+    fn test_protocol_version_negotiation() {
+        let server = TestServer::start();
+        let mut client = server.connect();
+        client.expect(Command::PluginState);
+
+        assert_eq!(client.declare_protocol_version(99), 4);
+        assert_eq!(client.declare_protocol_version(1), 1);
+        assert_eq!(client.declare_protocol_version(0), 0);
+    }
+
+    #[test]
+    fn test_messagepack_encoding() {
+        let server = TestServer::start();
+        let mut client = server.connect();
+        client.expect(Command::PluginState);
+
+        // older protocol versions stay on json
+        assert_eq!(client.declare_protocol(2, 1).get("Encoding"), None);
+        client.expect_only_pong(SERVER_UID);
+        assert_eq!(client.binary_frames, 0);
+
+        assert_eq!(client.declare_protocol(3, 1)["Encoding"], 1);
+        client.send_binary(Command::Ping, SERVER_UID, serde_json::Value::Null);
+        let message = client.expect(Command::Pong);
+        assert_eq!(message["ServerUniqueIdentifier"], SERVER_UID);
+        assert_eq!(client.binary_frames, 1);
+
+        // text frames are still understood
+        client.expect_only_pong(SERVER_UID);
+        assert_eq!(client.binary_frames, 2);
+    }
+
+    #[test]
+    fn test_stock_client_gets_no_extension_messages() {
+        let server = connected_server();
+        let (server_id, _) = own_client(&server);
+        server.ts.add_client(server_id, "John Doe", GAME_CHANNEL);
+        let mut client = server.connect();
+        client.expect(Command::PluginState);
+
+        client.initiate(SERVER_UID, "John Doe", GAME_CHANNEL.0, &[]);
+
+        client.expect_only_pong(SERVER_UID);
+    }
+
+    #[test]
+    fn test_browser_origin_rejected() {
+        let server = TestServer::start();
+
+        assert!(server
+            .connect_with_origin(Some("https://example.com"))
+            .is_none());
+        assert!(server
+            .ts
+            .logs()
+            .iter()
+            .any(|log| log.contains("origin https://example.com")));
+        assert!(server
+            .connect_with_origin(Some("https://cfx-nui-x.evil.com"))
+            .is_none());
+
+        let mut client = server.connect_with_origin(Some("nui://game")).unwrap();
+        client.expect(Command::PluginState);
+        // native clients send no origin at all
+        let mut client = server.connect_with_origin(None).unwrap();
+        client.expect(Command::PluginState);
+    }
+
+    #[test]
+    fn test_initiate_requires_token() {
+        let server = TestServer::start_with(
+            SecurityConfig {
+                token: Some("secret".to_owned()),
+                ..SecurityConfig::default()
+            },
+            Limits::default(),
+        );
+        let server_id = server.ts.connect(SERVER_UID, "TS Nickname", LOBBY);
+        server.ts.add_channel(server_id, GAME_CHANNEL, "Game");
+
+        let mut client = server.connect();
+        client.declare_protocol_version(1);
+        client.initiate(SERVER_UID, "John Doe", GAME_CHANNEL.0, &[]);
+        let message = client.expect(Command::Error);
+        assert_eq!(message["Parameter"]["Error"], 200);
+        assert_eq!(server.game_ref.lock().unwrap().active_instances(), 0);
+
+        client.authenticate("wrong");
+        assert!(client.is_closed());
+        let rejections = server.ts.logs();
+        assert_eq!(
+            rejections
+                .iter()
+                .filter(|log| log.starts_with("Rejected"))
+                .count(),
+            2
+        );
+
+        let mut client = server.connect();
+        client.authenticate("secret");
+        client.initiate(SERVER_UID, "John Doe", GAME_CHANNEL.0, &[]);
+        client.sync(SERVER_UID);
+        assert_eq!(server.game_ref.lock().unwrap().active_instances(), 1);
+    }
+
+    #[test]
+    fn test_commands_require_token() {
+        let server = TestServer::start_with(
+            SecurityConfig {
+                token: Some("secret".to_owned()),
+                ..SecurityConfig::default()
+            },
+            Limits::default(),
+        );
+        let server_id = server.ts.connect(SERVER_UID, "TS Nickname", LOBBY);
+        server.ts.add_channel(server_id, GAME_CHANNEL, "Game");
+        let mut game = server.connect();
+        game.declare_protocol_version(4);
+        game.authenticate("secret");
+        game.initiate(SERVER_UID, "John Doe", GAME_CHANNEL.0, &[]);
+        game.sync(SERVER_UID);
+        server.apply_moves();
+        game.expect(Command::InstanceState);
+
+        let mut page = server.connect();
+        page.declare_protocol_version(4);
+        for command in [Command::Reset, Command::Subscribe, Command::BulkUpdate] {
+            page.send(command, SERVER_UID, serde_json::Value::Null);
+            let message = page.expect(Command::Error);
+            assert_eq!(message["Parameter"]["Error"], 200, "{:?}", command);
+        }
+        page.sync(SERVER_UID);
+        assert_eq!(server.game_ref.lock().unwrap().active_instances(), 1);
+
+        // a missing token is an error reply, not a crash
+        page.send(Command::Authenticate, SERVER_UID, serde_json::Value::Null);
+        let message = page.expect(Command::Error);
+        assert_eq!(message["Parameter"]["Error"], 6);
+        page.expect_only_pong(SERVER_UID);
+    }
+
+    #[test]
+    fn test_only_the_initiating_game_drives_an_instance() {
+        let server = connected_server();
+        let mut game = server.connect();
+        game.declare_protocol_version(4);
+        game.initiate(SERVER_UID, "John Doe", GAME_CHANNEL.0, &[]);
+        game.sync(SERVER_UID);
+        server.apply_moves();
+        game.expect(Command::InstanceState);
+
+        let mut other = server.connect();
+        other.declare_protocol_version(4);
+        other.reset(SERVER_UID);
+        let message = other.expect(Command::Error);
+        assert_eq!(message["Parameter"]["Error"], 200);
+        other.send(Command::Reset, "other-server-uid", serde_json::Value::Null);
+        other.expect(Command::Error);
+        other.sync(SERVER_UID);
+
+        assert_eq!(server.game_ref.lock().unwrap().active_instances(), 1);
+        let (server_id, own_client_id) = own_client(&server);
+        assert_eq!(
+            server.ts.client_channel(server_id, own_client_id).unwrap(),
+            GAME_CHANNEL
+        );
+
+        game.reset(SERVER_UID);
+        game.sync(SERVER_UID);
+        assert_eq!(server.game_ref.lock().unwrap().active_instances(), 0);
+    }
+
+    #[test]
+    fn test_missing_parameter_is_an_error() {
+        let server = connected_server();
+        let mut client = server.connect();
+        client.declare_protocol_version(4);
+        client.initiate(SERVER_UID, "John Doe", GAME_CHANNEL.0, &[]);
+        client.sync(SERVER_UID);
+
+        for command in [
+            Command::ProtocolVersion,
+            Command::Subscribe,
+            Command::SelfStateUpdate,
+            Command::BulkUpdate,
+            Command::PlayerStateDelta,
+            Command::RadioCommunicationUpdate,
+            Command::UpdateRadioChannelMembers,
+        ] {
+            client.send(command, SERVER_UID, serde_json::Value::Null);
+            let message = client.expect(Command::Error);
+            assert_eq!(message["Parameter"]["Error"], 6, "{:?}", command);
+        }
+
+        client.send_raw(r#"{"Command":104,"ServerUniqueIdentifier":null,"Parameter":{"Role":1}}"#);
+        let message = client.expect(Command::Error);
+        assert_eq!(message["Parameter"]["Error"], 6);
+        client.expect_only_pong(SERVER_UID);
+    }
+
+    #[test]
+    fn test_message_limits() {
+        let server = TestServer::start_with(
+            SecurityConfig::default(),
+            Limits {
+                max_message_size: 1024,
+                max_bulk_players: 2,
+                ..Limits::default()
+            },
+        );
+        let server_id = server.ts.connect(SERVER_UID, "TS Nickname", LOBBY);
+        server.ts.add_channel(server_id, GAME_CHANNEL, "Game");
+        let mut client = server.connect();
+        client.declare_protocol_version(1);
+        client.initiate(SERVER_UID, "John Doe", GAME_CHANNEL.0, &[]);
+        client.sync(SERVER_UID);
+
+        client.send_raw(&" ".repeat(2048));
+        let message = client.expect(Command::Error);
+        assert_eq!(message["Parameter"]["Error"], 202);
+        assert!(client.is_closed());
+
+        let mut client = server.connect();
+        client.declare_protocol_version(1);
+        client.initiate(SERVER_UID, "John Doe", GAME_CHANNEL.0, &[]);
+        client.sync(SERVER_UID);
+
+        client.bulk_update(
+            SERVER_UID,
+            &[
+                ("Jane Doe", [1.0, 2.0, 3.0]),
+                ("Max Mustermann", [10.0, 0.0, 0.0]),
+                ("Erika Mustermann", [0.0, 10.0, 0.0]),
+            ],
+            [0.0, 0.0, 0.0],
+        );
+        let message = client.expect(Command::Error);
+        assert_eq!(message["Parameter"]["Error"], 203);
+        assert_eq!(message["ServerUniqueIdentifier"], SERVER_UID);
+
+        client.expect_only_pong(SERVER_UID);
+    }
 
     #[test]
-    fn test_websocket_server() {
-        //start_listen();
-        std::thread::sleep(std::time::Duration::from_millis(250));
+    fn test_oversized_frame_is_refused_before_its_payload() {
+        let server = TestServer::start_with(
+            SecurityConfig::default(),
+            Limits {
+                max_message_size: 1024,
+                ..Limits::default()
+            },
+        );
+        let mut client = server.connect();
+        client.expect(Command::PluginState);
+
+        client.announce_text_frame(1 << 30);
+        let message = client.expect(Command::Error);
+        assert_eq!(message["Parameter"]["Error"], 202);
+        assert!(client.is_closed());
+    }
+
+    #[test]
+    fn test_flooding_client_is_rate_limited() {
+        let server = TestServer::start_with(
+            SecurityConfig::default(),
+            Limits {
+                max_messages_per_second: 5,
+                ..Limits::default()
+            },
+        );
+        let mut client = server.connect();
+        client.expect(Command::PluginState);
+        client.declare_protocol_version(1);
+
+        for _ in 0..20 {
+            client.send(Command::Ping, SERVER_UID, serde_json::Value::Null);
+        }
+
+        let mut pongs = 0;
+        let mut errors = 0;
+        while let Some(message) = client.receive(std::time::Duration::from_millis(300)) {
+            if message["Command"] == Command::Pong as u32 {
+                pongs += 1;
+            } else if message["Command"] == Command::Error as u32 {
+                assert_eq!(message["Parameter"]["Error"], 201);
+                errors += 1;
+            }
+        }
+        assert!(pongs < 20, "{} pongs", pongs);
+        assert_eq!(errors, 1);
+    }
+
+    #[test]
+    fn test_events_fan_out_to_observers() {
+        let server = connected_server();
+        let mut game = server.connect();
+        game.declare_protocol_version(4);
+        game.initiate(SERVER_UID, "John Doe", GAME_CHANNEL.0, &[]);
+        game.sync(SERVER_UID);
+        server.apply_moves();
+        game.expect(Command::InstanceState);
+
+        let mut overlay = server.connect();
+        overlay.declare_protocol_version(4);
+        overlay.subscribe(SERVER_UID, 1);
+        overlay.sync(SERVER_UID);
+
+        super::on_talk_state_toggle(&SERVER_UID.to_owned(), true, "Jane Doe").unwrap();
+        super::on_error(&SERVER_UID.to_owned(), super::Error::InvalidValue, "test").unwrap();
+        for client in [&mut game, &mut overlay] {
+            let message = client.expect(Command::TalkState);
+            assert_eq!(message["Parameter"]["Name"], "Jane Doe");
+        }
+        game.expect(Command::Error);
+        overlay.expect_only_pong(SERVER_UID);
+
+        // observers can't drive the instance and leaving doesn't end it
+        overlay.reset(SERVER_UID);
+        overlay.sync(SERVER_UID);
+        drop(overlay);
+        game.expect_only_pong(SERVER_UID);
+        assert_eq!(server.game_ref.lock().unwrap().active_instances(), 1);
+        wait_for(|| CLIENTS_BY_INSTANCE.lock().unwrap()[SERVER_UID].len() == 1);
+    }
+
+    #[test]
+    fn test_gone_subscriber_does_not_block_delivery() {
+        let server = connected_server();
+        let mut game = server.connect();
+        game.initiate(SERVER_UID, "John Doe", GAME_CHANNEL.0, &[]);
+        game.sync(SERVER_UID);
+        server.apply_moves();
+        game.expect(Command::InstanceState);
+
+        CLIENTS_BY_INSTANCE
+            .lock()
+            .unwrap()
+            .get_mut(SERVER_UID)
+            .unwrap()
+            .insert(
+                0,
+                Subscriber {
+                    client_id: u64::MAX,
+                    role: SubscriberRole::Observer,
+                },
+            );
+
+        super::on_talk_state_toggle(&SERVER_UID.to_owned(), true, "Jane Doe").unwrap();
+        let message = game.expect(Command::TalkState);
+        assert_eq!(message["Parameter"]["Name"], "Jane Doe");
+        assert_eq!(CLIENTS_BY_INSTANCE.lock().unwrap()[SERVER_UID].len(), 1);
+    }
+
+    #[test]
+    fn test_stale_client_is_reset() {
+        let server = TestServer::start_with(
+            SecurityConfig::default(),
+            Limits {
+                heartbeat_timeout: Duration::from_millis(200),
+                ..Limits::default()
+            },
+        );
+        let server_id = server.ts.connect(SERVER_UID, "TS Nickname", LOBBY);
+        server.ts.add_channel(server_id, GAME_CHANNEL, "Game");
+        let mut client = server.connect();
+        client.initiate(SERVER_UID, "John Doe", GAME_CHANNEL.0, &[]);
+        client.sync(SERVER_UID);
+        server.apply_moves();
+        assert_eq!(server.game_ref.lock().unwrap().active_instances(), 1);
+
+        // the client doesn't read, so the ping stays unanswered
+        wait_for(|| server.game_ref.lock().unwrap().active_instances() == 0);
+        assert!(client.is_closed());
+
+        let (server_id, own_client_id) = own_client(&server);
+        assert_eq!(
+            server.ts.client_channel(server_id, own_client_id).unwrap(),
+            LOBBY
+        );
+        assert!(CLIENTS_BY_INSTANCE.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_idle_client_answering_pings_stays_connected() {
+        let server = TestServer::start_with(
+            SecurityConfig::default(),
+            Limits {
+                heartbeat_timeout: Duration::from_millis(200),
+                ..Limits::default()
+            },
+        );
+        let server_id = server.ts.connect(SERVER_UID, "TS Nickname", LOBBY);
+        server.ts.add_channel(server_id, GAME_CHANNEL, "Game");
+        let mut client = server.connect();
+        client.initiate(SERVER_UID, "John Doe", GAME_CHANNEL.0, &[]);
+        client.sync(SERVER_UID);
+        server.apply_moves();
+
+        // reading answers the pings, nothing else is sent for several timeouts
+        assert!(!client.is_closed());
+        assert_eq!(server.game_ref.lock().unwrap().active_instances(), 1);
+        assert_eq!(CLIENTS.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_disconnect_resets_instance() {
+        let server = connected_server();
+        let mut client = server.connect();
+        client.initiate(SERVER_UID, "John Doe", GAME_CHANNEL.0, &[]);
+        client.sync(SERVER_UID);
+        server.apply_moves();
+
+        drop(client);
+
+        wait_for(|| server.game_ref.lock().unwrap().active_instances() == 0);
+        assert!(CLIENTS_BY_INSTANCE.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_player_states_reach_the_session() {
+        let server = connected_server();
+        let (server_id, _) = own_client(&server);
+        let jane = server.ts.add_client(server_id, "Jane Doe", GAME_CHANNEL);
+        let mut client = server.connect();
+        client.declare_protocol_version(2);
+        client.initiate(SERVER_UID, "John Doe", GAME_CHANNEL.0, &[]);
+        client.sync(SERVER_UID);
+
+        client.bulk_update(
+            SERVER_UID,
+            &[("Jane Doe", [1.0, 2.0, 3.0])],
+            [0.0, 0.0, 0.0],
+        );
+        client.send(
+            Command::PlayerStateDelta,
+            SERVER_UID,
+            json!({ "PlayerStates": [{ "Name": "Jane Doe", "VoiceRange": 15.0 }] }),
+        );
+        client.sync(SERVER_UID);
+
+        let game = server.game_ref.lock().unwrap();
+        let player = game.player_state(server_id, jane).unwrap();
+        assert_eq!(player.name, "Jane Doe");
+        assert_eq!(player.position.x, 1.0);
+        assert_eq!(player.voice_range, 15.0);
+        drop(game);
+
+        client.send(
+            Command::RemovePlayer,
+            SERVER_UID,
+            json!({ "Name": "Jane Doe" }),
+        );
+        client.sync(SERVER_UID);
+        assert!(server
+            .game_ref
+            .lock()
+            .unwrap()
+            .player_state(server_id, jane)
+            .is_none());
+    }
+
+    #[test]
+    fn test_initiate_joins_game_channel() {
+        let server = connected_server();
+        let mut client = server.connect();
+        client.expect(Command::PluginState);
+
+        client.initiate(SERVER_UID, "John Doe", GAME_CHANNEL.0, &[SUPPORT_CHANNEL.0]);
+        client.sync(SERVER_UID);
+        server.apply_moves();
+
+        let message = client.expect(Command::InstanceState);
+        assert_eq!(message["ServerUniqueIdentifier"], SERVER_UID);
+        assert_eq!(message["Parameter"]["State"], 2);
+
+        let (server_id, own_client_id) = own_client(&server);
+        assert_eq!(
+            server.ts.client_name(server_id, own_client_id).unwrap(),
+            "John Doe"
+        );
+        assert_eq!(
+            server.ts.client_channel(server_id, own_client_id).unwrap(),
+            GAME_CHANNEL
+        );
+    }
+
+    #[test]
+    fn test_sound_state_reported() {
+        let server = connected_server();
+        let mut client = server.connect();
+        client.initiate(SERVER_UID, "John Doe", GAME_CHANNEL.0, &[]);
+
+        let message = client.expect(Command::SoundState);
+        assert_eq!(message["Parameter"]["IsMicrophoneMuted"], false);
+        assert_eq!(message["Parameter"]["IsMicrophoneEnabled"], true);
+        assert_eq!(message["Parameter"]["IsSoundMuted"], false);
+        assert_eq!(message["Parameter"]["IsSoundEnabled"], true);
+
+        let (server_id, _) = own_client(&server);
+        server.ts.set_input_muted(server_id, true).unwrap();
+        let sound_state_changed = || {
+            server
+                .game_ref
+                .lock()
+                .unwrap()
+                .ts_on_sound_state_changed(server.ts.as_ref(), server_id)
+        };
+        sound_state_changed();
+        let message = client.expect(Command::SoundState);
+        assert_eq!(message["Parameter"]["IsMicrophoneMuted"], true);
+
+        // unchanged states are not sent again
+        sound_state_changed();
+        client.expect_only_pong(SERVER_UID);
+    }
+
+    #[test]
+    fn test_talk_states_of_game_channel() {
+        let server = connected_server();
+        let (server_id, own_client_id) = own_client(&server);
+        let jane = server.ts.add_client(server_id, "Jane Doe", GAME_CHANNEL);
+        let max = server
+            .ts
+            .add_client(server_id, "Max Mustermann", SUPPORT_CHANNEL);
+        let mut client = server.connect();
+        client.initiate(SERVER_UID, "John Doe", GAME_CHANNEL.0, &[]);
+        client.sync(SERVER_UID);
+        server.apply_moves();
+        client.expect(Command::InstanceState);
+
+        let talking_changed = |connection_id, is_talking| {
+            server.game_ref.lock().unwrap().ts_on_talking_changed(
+                server.ts.as_ref(),
+                server_id,
+                connection_id,
+                is_talking,
+            )
+        };
+
+        talking_changed(jane, true);
+        let message = client.expect(Command::TalkState);
+        assert_eq!(message["Parameter"]["Name"], "Jane Doe");
+        assert_eq!(message["Parameter"]["IsTalking"], true);
+
+        talking_changed(own_client_id, true);
+        let message = client.expect(Command::TalkState);
+        assert_eq!(message["Parameter"]["Name"], "John Doe");
+
+        // players outside of the game channel are not part of the game
+        talking_changed(max, true);
+        client.expect_only_pong(SERVER_UID);
+    }
+
+    #[test]
+    fn test_talk_states_disabled() {
+        let server = connected_server();
+        let (server_id, own_client_id) = own_client(&server);
+        let jane = server.ts.add_client(server_id, "Jane Doe", GAME_CHANNEL);
+        let mut client = server.connect();
+        client.initiate_with(
+            SERVER_UID,
+            "John Doe",
+            GAME_CHANNEL.0,
+            &[],
+            json!({ "SendTalkStates": false }),
+        );
+        client.sync(SERVER_UID);
+        server.apply_moves();
+        client.expect(Command::InstanceState);
+
+        for connection_id in [jane, own_client_id] {
+            server.game_ref.lock().unwrap().ts_on_talking_changed(
+                server.ts.as_ref(),
+                server_id,
+                connection_id,
+                true,
+            );
+        }
+        client.expect_only_pong(SERVER_UID);
+    }
 
-        let (mut socket, _response) =
-            connect(Url::parse("ws://localhost:9151").unwrap()).expect("Can't connect");
+    #[test]
+    fn test_whisper_targets_follow_game_state() {
+        let server = connected_server();
+        let (server_id, _) = own_client(&server);
+        let jane = server.ts.add_client(server_id, "Jane Doe", GAME_CHANNEL);
+        let max = server
+            .ts
+            .add_client(server_id, "Max Mustermann", GAME_CHANNEL);
+        let mut client = server.connect();
+        client.initiate(SERVER_UID, "John Doe", GAME_CHANNEL.0, &[]);
+        client.sync(SERVER_UID);
+        server.apply_moves();
+
+        client.bulk_update(
+            SERVER_UID,
+            &[
+                ("Jane Doe", [1.0, 2.0, 3.0]),
+                ("Max Mustermann", [100.0, 0.0, 0.0]),
+            ],
+            [0.0, 0.0, 0.0],
+        );
+        client.sync(SERVER_UID);
+        assert_eq!(server.ts.whisper_list(server_id), vec![jane]);
+        assert!(server.game_ref.lock().unwrap().has_listeners(server_id));
+
+        client.phone_communication_update(SERVER_UID, "Max Mustermann", 3);
+        client.sync(SERVER_UID);
+        assert_eq!(server.ts.whisper_list(server_id), vec![jane, max]);
+
+        client.stop_phone_communication(SERVER_UID, "Max Mustermann");
+        client.bulk_update(SERVER_UID, &[], [0.0, 0.0, 0.0]);
+        client.sync(SERVER_UID);
+        assert!(server.ts.whisper_list(server_id).is_empty());
+        assert!(!server.game_ref.lock().unwrap().has_listeners(server_id));
+    }
+
+    #[test]
+    fn test_initiate_name_not_available() {
+        let server = connected_server();
+        let (server_id, _) = own_client(&server);
+        server.ts.add_client(server_id, "John Doe", GAME_CHANNEL);
+        let mut client = server.connect();
+        client.declare_protocol_version(1);
+
+        client.initiate(SERVER_UID, "John Doe", GAME_CHANNEL.0, &[]);
+
+        let message = client.expect(Command::Error);
+        assert_eq!(message["Parameter"]["Error"], 5);
+    }
+
+    #[test]
+    fn test_initiate_reports_fallback_nickname() {
+        let server = connected_server();
+        let (server_id, own_client_id) = own_client(&server);
+        server.ts.add_client(server_id, "John Doe", GAME_CHANNEL);
+        let mut client = server.connect();
+        client.declare_protocol_version(1);
+
+        client.initiate_with(
+            SERVER_UID,
+            "John Doe",
+            GAME_CHANNEL.0,
+            &[],
+            json!({ "NameFallbackAttempts": 2 }),
+        );
+
+        let message = client.expect(Command::Error);
+        assert_eq!(message["Parameter"]["Error"], 204);
+        assert_eq!(message["Parameter"]["Message"], "John Doe (2)");
+        assert_eq!(
+            server.ts.client_name(server_id, own_client_id).unwrap(),
+            "John Doe (2)"
+        );
+    }
+
+    #[test]
+    fn test_game_updates_keep_connection_alive() {
+        let server = connected_server();
+        let mut client = server.connect();
+        client.expect(Command::PluginState);
+        client.initiate(SERVER_UID, "John Doe", GAME_CHANNEL.0, &[]);
+        client.sync(SERVER_UID);
+        server.apply_moves();
+        client.expect(Command::InstanceState);
+
+        client.bulk_update(
+            SERVER_UID,
+            &[
+                ("Jane Doe", [1.0, 2.0, 3.0]),
+                ("Max Mustermann", [10.0, 0.0, 0.0]),
+            ],
+            [0.0, 0.0, 0.0],
+        );
+        client.radio_communication_update(SERVER_UID, "Jane Doe", false);
+        client.stop_radio_communication(SERVER_UID, "Jane Doe");
+        client.phone_communication_update(SERVER_UID, "Max Mustermann", 3);
+        client.stop_phone_communication(SERVER_UID, "Max Mustermann");
+        client.send_raw("not json");
 
-        socket
-            .write_message(tungstenite::Message::Text("Hello World".to_string()))
-            .unwrap();
+        client.expect_only_pong(SERVER_UID);
+    }
 
-        let message = socket.read_message().unwrap();
+    #[test]
+    fn test_swiss_channel_and_reset() {
+        let server = connected_server();
+        let mut client = server.connect();
+        client.initiate(SERVER_UID, "John Doe", GAME_CHANNEL.0, &[SUPPORT_CHANNEL.0]);
+        client.sync(SERVER_UID);
+        server.apply_moves();
+        client.expect(Command::InstanceState);
 
-        assert_eq!(message.to_text().unwrap(), "Hello World");
+        let (server_id, own_client_id) = own_client(&server);
+        server
+            .ts
+            .force_move(server_id, own_client_id, SUPPORT_CHANNEL);
+        server.game_ref.lock().unwrap().ts_on_channel_switched(
+            server.ts.as_ref(),
+            server_id,
+            own_client_id,
+            SUPPORT_CHANNEL,
+            ts3plugin::Visibility::Retain,
+        );
+        let message = client.expect(Command::InstanceState);
+        assert_eq!(message["Parameter"]["State"], 3);
+
+        server.ts.force_move(server_id, own_client_id, GAME_CHANNEL);
+        client.reset(SERVER_UID);
+        client.sync(SERVER_UID);
+
+        assert_eq!(server.game_ref.lock().unwrap().active_instances(), 0);
+        assert_eq!(
+            server.ts.client_channel(server_id, own_client_id).unwrap(),
+            LOBBY
+        );
     }
 }
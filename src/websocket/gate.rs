@@ -0,0 +1,486 @@
+use std::io::{self, Read};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use ts3plugin::LogLevel;
+use tungstenite::error::CapacityError;
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::http::StatusCode;
+use tungstenite::protocol::frame::coding::CloseCode;
+use tungstenite::protocol::{CloseFrame, WebSocketConfig};
+use tungstenite::WebSocket;
+
+use super::limits::Limits;
+use super::protocol::{Command, Error, ErrorParameter, ParamMessageType, ProtocolMessage};
+use crate::teamspeak::BackendProvider;
+
+const MAX_HANDSHAKE_SIZE: usize = 8 * 1024;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+// how long a client gets to answer the server's close frame
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+// how often a connection looks for messages to send while it waits for the client
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// origins the game clients' embedded browsers connect from, `*` stands for exactly one host label
+const DEFAULT_ALLOWED_ORIGINS: [&str; 4] = [
+    "nui://game",
+    "https://cfx-nui-*",
+    "package://*",
+    "http://resource",
+];
+
+// a message to or from a game client
+#[derive(Debug, Clone)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl Message {
+    fn into_tungstenite(self) -> tungstenite::Message {
+        match self {
+            Self::Text(text) => tungstenite::Message::Text(text),
+            Self::Binary(bin) => tungstenite::Message::Binary(bin),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Event {
+    Connect(u64, Responder),
+    Disconnect(u64),
+    Message(u64, Message),
+}
+
+// everything the connections report, in the order it happened
+pub struct EventHub {
+    events: Receiver<Event>,
+}
+
+impl EventHub {
+    pub fn poll_event(&self) -> Event {
+        self.events
+            .recv()
+            .expect("the websocket listener thread is gone")
+    }
+}
+
+enum ResponderCommand {
+    Send(Message),
+    Close,
+}
+
+// sends to one client, the connection is closed once every responder is dropped
+#[derive(Debug, Clone)]
+pub struct Responder {
+    commands: Sender<ResponderCommand>,
+}
+
+impl Responder {
+    // false once the client is gone
+    pub fn send(&self, message: Message) -> bool {
+        self.commands.send(ResponderCommand::Send(message)).is_ok()
+    }
+
+    pub fn close(&self) {
+        let _ = self.commands.send(ResponderCommand::Close);
+    }
+}
+
+// who may drive the ts client through the websocket
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SecurityConfig {
+    pub allowed_origins: Vec<String>,
+    // shared secret the game scripts have to send before Initiate is accepted
+    pub token: Option<String>,
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: DEFAULT_ALLOWED_ORIGINS
+                .iter()
+                .map(|origin| origin.to_string())
+                .collect(),
+            token: None,
+        }
+    }
+}
+
+impl SecurityConfig {
+    // RUSTYCHAT_ALLOWED_ORIGINS (comma separated) replaces the default origins,
+    // RUSTYCHAT_TOKEN enables the token handshake
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+
+        if let Ok(origins) = std::env::var("RUSTYCHAT_ALLOWED_ORIGINS") {
+            config.allowed_origins = origins
+                .split(',')
+                .map(|origin| origin.trim().to_owned())
+                .filter(|origin| !origin.is_empty())
+                .collect();
+        }
+        config.token = std::env::var("RUSTYCHAT_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty());
+
+        config
+    }
+
+    // browsers always send an origin, a missing one is a native client, which could connect
+    // with any origin it likes anyway
+    pub fn is_origin_allowed(&self, origin: Option<&str>) -> bool {
+        let origin = match origin {
+            Some(origin) => origin,
+            None => return true,
+        };
+
+        self.allowed_origins
+            .iter()
+            .any(|allowed| origin_matches(allowed, origin))
+    }
+
+    pub fn is_token_valid(&self, token: &str) -> bool {
+        match &self.token {
+            Some(expected) => constant_time_eq(expected.as_bytes(), token.as_bytes()),
+            None => true,
+        }
+    }
+}
+
+// the part matched by `*` has to be a single host label, so `https://cfx-nui-*` doesn't
+// let in `https://cfx-nui-x.evil.com`
+fn origin_matches(allowed: &str, origin: &str) -> bool {
+    let (prefix, suffix) = match allowed.split_once('*') {
+        Some(parts) => parts,
+        None => return origin.eq_ignore_ascii_case(allowed),
+    };
+
+    let origin = origin.to_ascii_lowercase();
+    match origin
+        .strip_prefix(&prefix.to_ascii_lowercase())
+        .and_then(|rest| rest.strip_suffix(&suffix.to_ascii_lowercase()))
+    {
+        Some(label) => {
+            !label.is_empty()
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        }
+        None => false,
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+// the websocket server itself, every connection is checked here before the game logic sees
+// it and nothing else listens for clients
+pub fn launch(
+    listener: TcpListener,
+    config: Arc<SecurityConfig>,
+    limits: Limits,
+    ts: Arc<dyn BackendProvider>,
+) -> EventHub {
+    let (events, receiver) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        for (client_id, stream) in (0..).zip(listener.incoming().flatten()) {
+            let events = events.clone();
+            let config = config.clone();
+            let ts = ts.clone();
+            std::thread::spawn(move || {
+                if let Err(err) =
+                    handle_connection(stream, client_id, &config, &limits, ts.as_ref(), &events)
+                {
+                    println!("Websocket connection error: {:?}", err);
+                }
+            });
+        }
+    });
+
+    EventHub { events: receiver }
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    client_id: u64,
+    config: &SecurityConfig,
+    limits: &Limits,
+    ts: &dyn BackendProvider,
+    events: &Sender<Event>,
+) -> Result<()> {
+    let max_message_size = limits.max_message_size;
+    wait_for_handshake(&stream)?;
+    let peer = stream.peer_addr()?;
+
+    // the error type is dictated by tungstenite's handshake callback
+    #[allow(clippy::result_large_err)]
+    let check_origin = |request: &Request, response: Response| {
+        // an origin that isn't valid utf-8 can't be allowed, but it isn't missing either
+        let origin = request
+            .headers()
+            .get("origin")
+            .map(|origin| origin.to_str().unwrap_or_default());
+        if config.is_origin_allowed(origin) {
+            return Ok(response);
+        }
+
+        let message = format!(
+            "Rejected websocket connection from {} with origin {}",
+            peer,
+            origin.unwrap_or_default()
+        );
+        ts.with_backend(&mut |ts| ts.log(&message, LogLevel::Warning));
+
+        let mut rejection = ErrorResponse::new(None);
+        *rejection.status_mut() = StatusCode::FORBIDDEN;
+        Err(rejection)
+    };
+    // the size is checked with each frame header, before the payload is read
+    let websocket_config = WebSocketConfig {
+        max_message_size: Some(max_message_size),
+        max_frame_size: Some(max_message_size),
+        ..WebSocketConfig::default()
+    };
+    let mut socket =
+        tungstenite::accept_hdr_with_config(stream, check_origin, Some(websocket_config))
+            .map_err(|err| anyhow!("websocket handshake with {} failed: {}", peer, err))?;
+    socket.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+
+    let (commands, receiver) = mpsc::channel();
+    if events
+        .send(Event::Connect(client_id, Responder { commands }))
+        .is_err()
+    {
+        return Ok(());
+    }
+
+    let too_large = serve(
+        &mut socket,
+        client_id,
+        limits.heartbeat_timeout,
+        &receiver,
+        events,
+    );
+    let _ = events.send(Event::Disconnect(client_id));
+
+    if let Some(size) = too_large {
+        let message = format!(
+            "Closed websocket connection from {}, message of {} bytes exceeds the limit of {} bytes",
+            peer, size, max_message_size
+        );
+        ts.with_backend(&mut |ts| ts.log(&message, LogLevel::Warning));
+        refuse_message(&mut socket, size, max_message_size)?;
+    }
+    Ok(())
+}
+
+// relays between the socket and the game logic until the connection is gone, a close
+// requested through the responder ends it once the client answered or CLOSE_TIMEOUT passed,
+// returns the size of the message above the limit that ended the connection
+//
+// any frame counts as a sign of life, a client that stays silent for `heartbeat_timeout` is
+// pinged and closed when it doesn't answer within another `heartbeat_timeout`
+fn serve(
+    socket: &mut WebSocket<TcpStream>,
+    client_id: u64,
+    heartbeat_timeout: Duration,
+    commands: &Receiver<ResponderCommand>,
+    events: &Sender<Event>,
+) -> Option<usize> {
+    let mut closing_since = None;
+    let mut last_heard = Instant::now();
+    let mut pinged = false;
+
+    loop {
+        let mut close_requested = loop {
+            match commands.try_recv() {
+                Ok(ResponderCommand::Send(message)) => {
+                    if closing_since.is_none()
+                        && socket.write_message(message.into_tungstenite()).is_err()
+                    {
+                        return None;
+                    }
+                }
+                Ok(ResponderCommand::Close) | Err(TryRecvError::Disconnected) => break true,
+                Err(TryRecvError::Empty) => break false,
+            }
+        };
+        let silence = last_heard.elapsed();
+        if closing_since.is_none() && silence > heartbeat_timeout {
+            if !pinged {
+                if socket
+                    .write_message(tungstenite::Message::Ping(Vec::new()))
+                    .is_err()
+                {
+                    return None;
+                }
+                pinged = true;
+            } else if silence > heartbeat_timeout * 2 {
+                println!(
+                    "Client #{} didn't answer for {:?}, closing connection",
+                    client_id, silence
+                );
+                close_requested = true;
+            }
+        }
+
+        match closing_since {
+            None if close_requested => {
+                if socket.close(None).is_err() {
+                    return None;
+                }
+                closing_since = Some(Instant::now());
+            }
+            Some(since) if since.elapsed() > CLOSE_TIMEOUT => return None,
+            _ => {}
+        }
+
+        let message = socket.read_message();
+        if message.is_ok() {
+            last_heard = Instant::now();
+            pinged = false;
+        }
+        let message = match message {
+            Ok(tungstenite::Message::Text(text)) => Message::Text(text),
+            Ok(tungstenite::Message::Binary(bin)) => Message::Binary(bin),
+            // pings and the close handshake are answered by tungstenite, pongs only keep the
+            // connection alive
+            Ok(_) => continue,
+            Err(tungstenite::Error::Io(err))
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut =>
+            {
+                continue
+            }
+            Err(tungstenite::Error::Capacity(CapacityError::MessageTooLong { size, .. })) => {
+                return Some(size)
+            }
+            Err(_) => return None,
+        };
+
+        if events.send(Event::Message(client_id, message)).is_err() {
+            return None;
+        }
+    }
+}
+
+// waits until the complete http upgrade request is buffered without consuming it, so a
+// client can't hold a connection open with a slow or endless handshake
+fn wait_for_handshake(stream: &TcpStream) -> Result<()> {
+    let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+    let mut buffer = vec![0; MAX_HANDSHAKE_SIZE];
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+
+    loop {
+        let len = stream.peek(&mut buffer)?;
+        if buffer[..len].windows(4).any(|w| w == b"\r\n\r\n") {
+            return Ok(());
+        }
+        if len == 0 || len == MAX_HANDSHAKE_SIZE || Instant::now() > deadline {
+            return Err(anyhow!("incomplete websocket handshake"));
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+// tells the game why before closing the connection with "message too big", the rest of the
+// message is read and dropped so the client gets to see the reply
+fn refuse_message(
+    socket: &mut WebSocket<TcpStream>,
+    size: usize,
+    max_message_size: usize,
+) -> Result<()> {
+    let error = ProtocolMessage {
+        command: Command::Error,
+        server_unique_identifier: None,
+        parameter: Some(ParamMessageType::ErrorParameter(ErrorParameter {
+            error: Error::MessageTooLarge,
+            message: format!(
+                "message of {} bytes exceeds the limit of {} bytes",
+                size, max_message_size
+            ),
+        })),
+    };
+
+    socket.write_message(tungstenite::Message::Text(serde_json::to_string(&error)?))?;
+    socket.close(Some(CloseFrame {
+        code: CloseCode::Size,
+        reason: "".into(),
+    }))?;
+    socket.write_pending()?;
+
+    let stream = socket.get_mut();
+    stream.shutdown(Shutdown::Write)?;
+    stream.set_read_timeout(Some(CLOSE_TIMEOUT))?;
+    let deadline = Instant::now() + CLOSE_TIMEOUT;
+    let mut discard = [0; 4096];
+    while Instant::now() < deadline && matches!(stream.read(&mut discard), Ok(len) if len > 0) {}
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_origins() {
+        let config = SecurityConfig::default();
+
+        assert!(config.is_origin_allowed(Some("nui://game")));
+        assert!(config.is_origin_allowed(Some("https://cfx-nui-saltychat")));
+        assert!(config.is_origin_allowed(Some("package://salty_chat")));
+        assert!(config.is_origin_allowed(None));
+        assert!(!config.is_origin_allowed(Some("https://example.com")));
+        assert!(!config.is_origin_allowed(Some("null")));
+    }
+
+    #[test]
+    fn test_look_alike_origins() {
+        let config = SecurityConfig::default();
+
+        for origin in [
+            "https://cfx-nui-x.evil.com",
+            "https://cfx-nui-.attacker.net",
+            "https://cfx-nui-",
+            "https://cfx-nui-saltychat:8080",
+            "https://cfx-nui-a@evil.com",
+            "package://evil.com",
+            "package://",
+            "package://game/../evil",
+            "nui://game.evil.com",
+        ] {
+            assert!(!config.is_origin_allowed(Some(origin)), "{}", origin);
+        }
+    }
+
+    #[test]
+    fn test_wildcard_in_the_middle() {
+        let config = SecurityConfig {
+            allowed_origins: vec!["https://*.example.com".to_owned()],
+            token: None,
+        };
+
+        assert!(config.is_origin_allowed(Some("https://game.example.com")));
+        assert!(config.is_origin_allowed(Some("https://Game.Example.com")));
+        assert!(!config.is_origin_allowed(Some("https://a.b.example.com")));
+        assert!(!config.is_origin_allowed(Some("https://.example.com")));
+        assert!(!config.is_origin_allowed(Some("https://game.example.com.evil.net")));
+    }
+
+    #[test]
+    fn test_token() {
+        let mut config = SecurityConfig::default();
+        assert!(config.is_token_valid(""));
+
+        config.token = Some("secret".to_owned());
+        assert!(config.is_token_valid("secret"));
+        assert!(!config.is_token_valid("secreT"));
+        assert!(!config.is_token_valid("secret2"));
+    }
+}
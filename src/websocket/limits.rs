@@ -0,0 +1,150 @@
+use std::time::{Duration, Instant};
+
+const DEFAULT_MAX_MESSAGES_PER_SECOND: u32 = 50;
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 512 * 1024;
+const DEFAULT_MAX_BULK_PLAYERS: usize = 512;
+const DEFAULT_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// how much a single game client may send before its traffic is dropped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    pub max_messages_per_second: u32,
+    // in bytes, enforced by the gate before the message is buffered
+    pub max_message_size: usize,
+    pub max_bulk_players: usize,
+    // clients that stay silent for this long are pinged, and closed when they don't answer
+    pub heartbeat_timeout: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_messages_per_second: DEFAULT_MAX_MESSAGES_PER_SECOND,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_bulk_players: DEFAULT_MAX_BULK_PLAYERS,
+            heartbeat_timeout: DEFAULT_HEARTBEAT_TIMEOUT,
+        }
+    }
+}
+
+impl Limits {
+    // RUSTYCHAT_MAX_MESSAGES_PER_SECOND, RUSTYCHAT_MAX_MESSAGE_SIZE,
+    // RUSTYCHAT_MAX_BULK_PLAYERS and RUSTYCHAT_HEARTBEAT_TIMEOUT (seconds) override the defaults,
+    // a rate of 0 would drop every message and a heartbeat timeout of 0 would ping without
+    // pause, both fall back to the default
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+
+        Self {
+            max_messages_per_second: env_or("RUSTYCHAT_MAX_MESSAGES_PER_SECOND")
+                .filter(|messages| *messages > 0)
+                .unwrap_or(defaults.max_messages_per_second),
+            max_message_size: env_or("RUSTYCHAT_MAX_MESSAGE_SIZE")
+                .unwrap_or(defaults.max_message_size),
+            max_bulk_players: env_or("RUSTYCHAT_MAX_BULK_PLAYERS")
+                .unwrap_or(defaults.max_bulk_players),
+            heartbeat_timeout: env_or("RUSTYCHAT_HEARTBEAT_TIMEOUT")
+                .filter(|seconds| *seconds > 0)
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.heartbeat_timeout),
+        }
+    }
+}
+
+fn env_or<T: std::str::FromStr>(name: &str) -> Option<T> {
+    std::env::var(name).ok()?.trim().parse().ok()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Admission {
+    Allowed,
+    // `report` is only set for the first dropped message, so the error itself can't flood
+    Dropped { report: bool },
+}
+
+// token bucket that allows bursts of up to one second worth of messages
+pub struct RateLimiter {
+    messages_per_second: f32,
+    tokens: f32,
+    last_refill: Instant,
+    limited: bool,
+}
+
+impl RateLimiter {
+    pub fn new(messages_per_second: u32, now: Instant) -> Self {
+        Self {
+            messages_per_second: messages_per_second as f32,
+            tokens: messages_per_second as f32,
+            last_refill: now,
+            limited: false,
+        }
+    }
+
+    pub fn admit(&mut self, now: Instant) -> Admission {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f32();
+        self.tokens =
+            (self.tokens + elapsed * self.messages_per_second).min(self.messages_per_second);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            self.limited = false;
+            return Admission::Allowed;
+        }
+
+        let report = !self.limited;
+        self.limited = true;
+        Admission::Dropped { report }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_rate_limiter_drops_burst() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(3, now);
+
+        for _ in 0..3 {
+            assert_eq!(limiter.admit(now), Admission::Allowed);
+        }
+        assert_eq!(limiter.admit(now), Admission::Dropped { report: true });
+        assert_eq!(limiter.admit(now), Admission::Dropped { report: false });
+    }
+
+    #[test]
+    fn test_rate_limiter_refills() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(2, now);
+        limiter.admit(now);
+        limiter.admit(now);
+        assert_eq!(limiter.admit(now), Admission::Dropped { report: true });
+
+        let later = now + Duration::from_millis(500);
+        assert_eq!(limiter.admit(later), Admission::Allowed);
+        assert_eq!(limiter.admit(later), Admission::Dropped { report: true });
+
+        // never more than one second worth of messages in the bucket
+        let much_later = later + Duration::from_secs(60);
+        assert_eq!(limiter.admit(much_later), Admission::Allowed);
+        assert_eq!(limiter.admit(much_later), Admission::Allowed);
+        assert_eq!(
+            limiter.admit(much_later),
+            Admission::Dropped { report: true }
+        );
+    }
+
+    #[test]
+    fn test_zero_limits_fall_back_to_default() {
+        std::env::set_var("RUSTYCHAT_MAX_MESSAGES_PER_SECOND", "0");
+        std::env::set_var("RUSTYCHAT_HEARTBEAT_TIMEOUT", "0");
+        assert_eq!(Limits::from_env(), Limits::default());
+        std::env::remove_var("RUSTYCHAT_MAX_MESSAGES_PER_SECOND");
+        std::env::remove_var("RUSTYCHAT_HEARTBEAT_TIMEOUT");
+    }
+}
@@ -1,7 +1,43 @@
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 
-#[derive(Serialize, Deserialize)]
+// SaltyChat plugin version reported to the game scripts
+pub const SALTY_CHAT_COMPAT_VERSION: &str = "2.3.6";
+
+// version of the RustyChat extensions, stock SaltyChat clients count as version 0
+pub const RUSTY_CHAT_PROTOCOL_VERSION: u32 = 4;
+
+// protocol features a client only gets once it declared a high enough version
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Extension {
+    ErrorMessages,
+    NameFallback,
+    PlayerStateDeltas,
+    MessagePack,
+    Subscribers,
+}
+
+impl Extension {
+    pub fn since_version(self) -> u32 {
+        match self {
+            Extension::ErrorMessages | Extension::NameFallback => 1,
+            Extension::PlayerStateDeltas => 2,
+            Extension::MessagePack => 3,
+            Extension::Subscribers => 4,
+        }
+    }
+
+    pub fn is_supported(self, protocol_version: u32) -> bool {
+        protocol_version >= self.since_version()
+    }
+}
+
+// highest version both sides understand
+pub fn negotiate_version(client_version: u32) -> u32 {
+    client_version.min(RUSTY_CHAT_PROTOCOL_VERSION)
+}
+
+#[derive(Serialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct ProtocolMessage {
     pub command: Command,
@@ -9,14 +45,85 @@ pub struct ProtocolMessage {
     pub parameter: Option<ParamMessageType>,
 }
 
+// the parameter shape depends on the command, the parameter is read straight into its type
+// when the command comes first, like it does from every saltychat script, otherwise it is
+// buffered until the command is known
+impl<'de> Deserialize<'de> for ProtocolMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(field_identifier, rename_all = "PascalCase")]
+        enum Field {
+            Command,
+            ServerUniqueIdentifier,
+            Parameter,
+            #[serde(other)]
+            Unknown,
+        }
+
+        struct MessageVisitor;
+
+        impl<'de> de::Visitor<'de> for MessageVisitor {
+            type Value = ProtocolMessage;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a protocol message")
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut command = None;
+                let mut server_unique_identifier = None;
+                let mut parameter = None;
+                let mut buffered_parameter = None;
+
+                while let Some(field) = map.next_key()? {
+                    match field {
+                        Field::Command => command = Some(map.next_value()?),
+                        Field::ServerUniqueIdentifier => {
+                            server_unique_identifier = map.next_value()?
+                        }
+                        Field::Parameter => match command {
+                            Some(command) => {
+                                parameter = map.next_value_seed(ParameterSeed(command))?
+                            }
+                            None => {
+                                buffered_parameter = Some(map.next_value::<serde_json::Value>()?)
+                            }
+                        },
+                        Field::Unknown => {
+                            map.next_value::<de::IgnoredAny>()?;
+                        }
+                    }
+                }
+
+                let command = command.ok_or_else(|| de::Error::missing_field("Command"))?;
+                if let Some(value) = buffered_parameter {
+                    parameter = de::DeserializeSeed::deserialize(ParameterSeed(command), value)
+                        .map_err(de::Error::custom)?;
+                }
+
+                Ok(ProtocolMessage {
+                    command,
+                    server_unique_identifier,
+                    parameter,
+                })
+            }
+        }
+
+        deserializer.deserialize_map(MessageVisitor)
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct PluginStateParameter {
     pub version: String,
     pub active_instances: u32,
+    // RustyChat extension, missing when talking to a stock SaltyChat plugin
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub rusty_chat_version: u32,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "PascalCase")]
 pub struct InitiateParameter {
     pub server_unique_identifier: String,
@@ -26,7 +133,7 @@ pub struct InitiateParameter {
     pub sound_pack: String,
     pub swiss_channel_ids: Vec<u64>,
     #[serde(default = "default_talk_state")]
-    send_talk_states: bool,
+    pub send_talk_states: bool,
     #[serde(default = "default_radio_traffic_state")]
     send_radio_traffic_states: bool,
     #[serde(default = "default_ultra_short_range_distance")]
@@ -35,6 +142,16 @@ pub struct InitiateParameter {
     pub short_range_distance: f32,
     #[serde(default = "default_long_range_distance")]
     pub long_range_distance: f32,
+    // RustyChat extension, number of "<name> (n)" fallbacks tried when the name is taken
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub name_fallback_attempts: u8,
+    // RustyChat extension, protocol version of the game scripts
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub rusty_chat_version: u32,
+}
+
+fn is_zero<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
 }
 
 fn default_talk_state() -> bool {
@@ -57,6 +174,76 @@ fn default_long_range_distance() -> f32 {
     8000.0
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ErrorParameter {
+    pub error: Error,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ProtocolVersionParameter {
+    pub version: u32,
+    // encoding the plugin uses for everything after the handshake reply
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub encoding: Encoding,
+}
+
+// frame encoding of a connection, text frames are always json and binary frames messagepack
+#[derive(Serialize_repr, Deserialize_repr, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[repr(u8)]
+pub enum Encoding {
+    #[default]
+    Json = 0,
+    MessagePack = 1,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct AuthenticateParameter {
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SubscribeParameter {
+    pub role: SubscriberRole,
+}
+
+// why a websocket client is attached to an instance
+#[derive(Serialize_repr, Deserialize_repr, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[repr(u8)]
+pub enum SubscriberRole {
+    // the game that sent Initiate and drives the instance
+    #[default]
+    Game = 0,
+    // read-only listener like a stream overlay
+    Observer = 1,
+}
+
+impl SubscriberRole {
+    pub fn may_send(self, command: Command) -> bool {
+        match self {
+            SubscriberRole::Game => true,
+            SubscriberRole::Observer => matches!(
+                command,
+                Command::Ping
+                    | Command::ProtocolVersion
+                    | Command::Authenticate
+                    | Command::Subscribe
+            ),
+        }
+    }
+
+    pub fn receives(self, command: Command) -> bool {
+        match self {
+            SubscriberRole::Game => true,
+            SubscriberRole::Observer => command != Command::Error,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct InstanceStateParameter {
@@ -137,6 +324,29 @@ fn default_intensity() -> i32 {
     10
 }
 
+// RustyChat extension, only the fields that changed since the last full player state
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PlayerStateDelta {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<Vector3>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotation: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub voice_range: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_alive: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub distance_culled: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PlayerStateDeltaParameter {
+    pub player_states: Vec<PlayerStateDelta>,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct BulkUpdateParameter {
@@ -184,7 +394,7 @@ pub struct PhoneCommunicationUpdateParameter {
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct StopPhoneCommunicationParameter {
-    name: String,
+    pub name: String,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -302,6 +512,14 @@ pub enum Error {
     InvalidValue = 6,
     ServerBlacklisted = 100,
     ServerUnderlicensed = 101,
+
+    // RustyChat
+    Unauthorized = 200,
+    TooManyMessages = 201,
+    MessageTooLarge = 202,
+    TooManyPlayers = 203,
+    // the requested name was taken, the message carries the nickname used instead
+    NameFallback = 204,
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy)]
@@ -312,7 +530,9 @@ pub struct Vector3 {
     pub z: f32,
 }
 
-#[derive(Serialize, Deserialize)]
+// the variants are named after the parameter structs of the saltychat protocol
+#[allow(clippy::enum_variant_names)]
+#[derive(Serialize)]
 #[serde(rename_all = "PascalCase")]
 #[serde(untagged)]
 pub enum ParamMessageType {
@@ -338,9 +558,85 @@ pub enum ParamMessageType {
     RemoveRadioChannelMemberParameter(RemoveRadioChannelMemberParameter),
     MegaphoneCommunicationUpdateParameter(MegaphoneCommunicationUpdateParameter),
     StopMegaphoneCommunicationParameter(StopMegaphoneCommunicationParameter),
-}
-
-#[derive(Serialize_repr, Deserialize_repr)]
+    ErrorParameter(ErrorParameter),
+    ProtocolVersionParameter(ProtocolVersionParameter),
+    AuthenticateParameter(AuthenticateParameter),
+    PlayerStateDeltaParameter(PlayerStateDeltaParameter),
+    SubscribeParameter(SubscribeParameter),
+}
+
+// reads the parameter of the given command
+struct ParameterSeed(Command);
+
+impl<'de> de::DeserializeSeed<'de> for ParameterSeed {
+    type Value = Option<ParamMessageType>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        use ParamMessageType as Param;
+
+        // a null parameter counts as a missing one
+        fn read<'de, T: Deserialize<'de>, D: Deserializer<'de>>(
+            deserializer: D,
+            variant: fn(T) -> ParamMessageType,
+        ) -> Result<Option<ParamMessageType>, D::Error> {
+            Ok(Option::<T>::deserialize(deserializer)?.map(variant))
+        }
+
+        match self.0 {
+            Command::PluginState => read(deserializer, Param::PluginStateParameter),
+            Command::Initiate => read(deserializer, Param::InitiateParameter),
+            Command::Reset | Command::Ping | Command::Pong => {
+                de::IgnoredAny::deserialize(deserializer)?;
+                Ok(None)
+            }
+            Command::InstanceState => read(deserializer, Param::InstanceStateParameter),
+            Command::SoundState => read(deserializer, Param::SoundStateParameter),
+            Command::SelfStateUpdate => read(deserializer, Param::SelfStateUpdateParameter),
+            Command::PlayerStateUpdate => read(deserializer, Param::PlayerStateUpdateParameter),
+            Command::BulkUpdate => read(deserializer, Param::BulkUpdateParameter),
+            Command::RemovePlayer => read(deserializer, Param::RemovePlayerParameter),
+            Command::TalkState => read(deserializer, Param::TalkStateParameter),
+            Command::PlaySound => read(deserializer, Param::PlaySoundParameter),
+            Command::StopSound => read(deserializer, Param::StopSoundParameter),
+            Command::PhoneCommunicationUpdate => {
+                read(deserializer, Param::PhoneCommunicationUpdateParameter)
+            }
+            Command::StopPhoneCommunication => {
+                read(deserializer, Param::StopPhoneCommunicationParameter)
+            }
+            Command::RadioCommunicationUpdate => {
+                read(deserializer, Param::RadioCommunicationUpdateParameter)
+            }
+            Command::StopRadioCommunication => {
+                read(deserializer, Param::StopRadioCommunicationParameter)
+            }
+            Command::RadioTowerUpdate => read(deserializer, Param::RadioTowerUpdateParameter),
+            Command::RadioTrafficState => read(deserializer, Param::RadioTrafficStateParameter),
+            Command::AddRadioChannelMember => {
+                read(deserializer, Param::AddRadioChannelMemberParameter)
+            }
+            Command::UpdateRadioChannelMembers => {
+                read(deserializer, Param::UpdateRadioChannelMembersParameter)
+            }
+            Command::RemoveRadioChannelMember => {
+                read(deserializer, Param::RemoveRadioChannelMemberParameter)
+            }
+            Command::MegaphoneCommunicationUpdate => {
+                read(deserializer, Param::MegaphoneCommunicationUpdateParameter)
+            }
+            Command::StopMegaphoneCommunication => {
+                read(deserializer, Param::StopMegaphoneCommunicationParameter)
+            }
+            Command::Error => read(deserializer, Param::ErrorParameter),
+            Command::ProtocolVersion => read(deserializer, Param::ProtocolVersionParameter),
+            Command::Authenticate => read(deserializer, Param::AuthenticateParameter),
+            Command::PlayerStateDelta => read(deserializer, Param::PlayerStateDeltaParameter),
+            Command::Subscribe => read(deserializer, Param::SubscribeParameter),
+        }
+    }
+}
+
+#[derive(Serialize_repr, Deserialize_repr, Clone, Copy, PartialEq, Eq, Debug)]
 #[repr(u32)]
 pub enum Command {
     // Plugin
@@ -378,4 +674,282 @@ pub enum Command {
     // Megaphone
     MegaphoneCommunicationUpdate = 40,
     StopMegaphoneCommunication = 41,
+
+    // RustyChat
+    Error = 100,
+    ProtocolVersion = 101,
+    Authenticate = 102,
+    PlayerStateDelta = 103,
+    Subscribe = 104,
+}
+
+impl Command {
+    // extension a client has to support before the plugin sends it this command
+    pub fn extension(self) -> Option<Extension> {
+        match self {
+            Command::Error => Some(Extension::ErrorMessages),
+            _ => None,
+        }
+    }
+
+    // everything but the handshake needs the token first
+    pub fn requires_authentication(self) -> bool {
+        !matches!(
+            self,
+            Command::Ping | Command::ProtocolVersion | Command::Authenticate
+        )
+    }
+
+    // commands only the game that initiated the instance may send
+    pub fn drives_instance(self) -> bool {
+        !matches!(
+            self,
+            Command::Ping
+                | Command::ProtocolVersion
+                | Command::Authenticate
+                | Command::Initiate
+                | Command::Subscribe
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    // hand written from the saltychat 2.x message definitions, not captured traffic
+    const SALTYCHAT_FIXTURES: &str = include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/saltychat-2.x.jsonl"
+    ));
+    const RUSTYCHAT_FIXTURES: &str = include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/rustychat-extensions.jsonl"
+    ));
+
+    const ALL_COMMANDS: [Command; 30] = [
+        Command::PluginState,
+        Command::Initiate,
+        Command::Reset,
+        Command::Ping,
+        Command::Pong,
+        Command::InstanceState,
+        Command::SoundState,
+        Command::SelfStateUpdate,
+        Command::PlayerStateUpdate,
+        Command::BulkUpdate,
+        Command::RemovePlayer,
+        Command::TalkState,
+        Command::PlaySound,
+        Command::StopSound,
+        Command::PhoneCommunicationUpdate,
+        Command::StopPhoneCommunication,
+        Command::RadioCommunicationUpdate,
+        Command::StopRadioCommunication,
+        Command::RadioTowerUpdate,
+        Command::RadioTrafficState,
+        Command::AddRadioChannelMember,
+        Command::UpdateRadioChannelMembers,
+        Command::RemoveRadioChannelMember,
+        Command::MegaphoneCommunicationUpdate,
+        Command::StopMegaphoneCommunication,
+        // RustyChat
+        Command::Error,
+        Command::ProtocolVersion,
+        Command::Authenticate,
+        Command::PlayerStateDelta,
+        Command::Subscribe,
+    ];
+
+    // `//` lines are comments
+    fn fixture_lines(fixtures: &'static str) -> impl Iterator<Item = &'static str> {
+        fixtures
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with("//"))
+    }
+
+    fn parse(line: &str) -> ProtocolMessage {
+        serde_json::from_str(line).unwrap_or_else(|err| panic!("{}: {}", err, line))
+    }
+
+    // f32 values only compare equal after going through the textual representation
+    fn round_trip(line: &str) -> Value {
+        serde_json::from_str(&serde_json::to_string(&parse(line)).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_saltychat_fixtures_round_trip() {
+        for line in fixture_lines(SALTYCHAT_FIXTURES).chain(fixture_lines(RUSTYCHAT_FIXTURES)) {
+            let expected: Value = serde_json::from_str(line).unwrap();
+            assert_eq!(round_trip(line), expected, "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_fixtures_round_trip_as_messagepack() {
+        for line in fixture_lines(SALTYCHAT_FIXTURES).chain(fixture_lines(RUSTYCHAT_FIXTURES)) {
+            let bytes = rmp_serde::to_vec_named(&parse(line)).unwrap();
+            let message: ProtocolMessage =
+                rmp_serde::from_slice(&bytes).unwrap_or_else(|err| panic!("{}: {}", err, line));
+
+            assert_eq!(
+                serde_json::from_str::<Value>(&serde_json::to_string(&message).unwrap()).unwrap(),
+                round_trip(line),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn test_fixtures_cover_every_command() {
+        let commands: Vec<Command> = fixture_lines(SALTYCHAT_FIXTURES)
+            .chain(fixture_lines(RUSTYCHAT_FIXTURES))
+            .map(|line| parse(line).command)
+            .collect();
+
+        for command in ALL_COMMANDS {
+            assert!(commands.contains(&command), "no fixture for {:?}", command);
+        }
+    }
+
+    // no wildcard, a new command has to be added here together with its parameter
+    fn has_expected_parameter(message: &ProtocolMessage) -> bool {
+        use ParamMessageType as Param;
+
+        let param = match (&message.command, &message.parameter) {
+            (Command::Reset | Command::Ping | Command::Pong, param) => return param.is_none(),
+            (_, None) => return false,
+            (_, Some(param)) => param,
+        };
+        match message.command {
+            Command::Reset | Command::Ping | Command::Pong => false,
+            Command::PluginState => matches!(param, Param::PluginStateParameter(_)),
+            Command::Initiate => matches!(param, Param::InitiateParameter(_)),
+            Command::InstanceState => matches!(param, Param::InstanceStateParameter(_)),
+            Command::SoundState => matches!(param, Param::SoundStateParameter(_)),
+            Command::SelfStateUpdate => matches!(param, Param::SelfStateUpdateParameter(_)),
+            Command::PlayerStateUpdate => matches!(param, Param::PlayerStateUpdateParameter(_)),
+            Command::BulkUpdate => matches!(param, Param::BulkUpdateParameter(_)),
+            Command::RemovePlayer => matches!(param, Param::RemovePlayerParameter(_)),
+            Command::TalkState => matches!(param, Param::TalkStateParameter(_)),
+            Command::PlaySound => matches!(param, Param::PlaySoundParameter(_)),
+            Command::StopSound => matches!(param, Param::StopSoundParameter(_)),
+            Command::PhoneCommunicationUpdate => {
+                matches!(param, Param::PhoneCommunicationUpdateParameter(_))
+            }
+            Command::StopPhoneCommunication => {
+                matches!(param, Param::StopPhoneCommunicationParameter(_))
+            }
+            Command::RadioCommunicationUpdate => {
+                matches!(param, Param::RadioCommunicationUpdateParameter(_))
+            }
+            Command::StopRadioCommunication => {
+                matches!(param, Param::StopRadioCommunicationParameter(_))
+            }
+            Command::RadioTowerUpdate => matches!(param, Param::RadioTowerUpdateParameter(_)),
+            Command::RadioTrafficState => matches!(param, Param::RadioTrafficStateParameter(_)),
+            Command::AddRadioChannelMember => {
+                matches!(param, Param::AddRadioChannelMemberParameter(_))
+            }
+            Command::UpdateRadioChannelMembers => {
+                matches!(param, Param::UpdateRadioChannelMembersParameter(_))
+            }
+            Command::RemoveRadioChannelMember => {
+                matches!(param, Param::RemoveRadioChannelMemberParameter(_))
+            }
+            Command::MegaphoneCommunicationUpdate => {
+                matches!(param, Param::MegaphoneCommunicationUpdateParameter(_))
+            }
+            Command::StopMegaphoneCommunication => {
+                matches!(param, Param::StopMegaphoneCommunicationParameter(_))
+            }
+            Command::Error => matches!(param, Param::ErrorParameter(_)),
+            Command::ProtocolVersion => matches!(param, Param::ProtocolVersionParameter(_)),
+            Command::Authenticate => matches!(param, Param::AuthenticateParameter(_)),
+            Command::PlayerStateDelta => matches!(param, Param::PlayerStateDeltaParameter(_)),
+            Command::Subscribe => matches!(param, Param::SubscribeParameter(_)),
+        }
+    }
+
+    #[test]
+    fn test_parameter_resolved_by_command() {
+        for line in fixture_lines(SALTYCHAT_FIXTURES).chain(fixture_lines(RUSTYCHAT_FIXTURES)) {
+            assert!(has_expected_parameter(&parse(line)), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_missing_optional_fields_use_saltychat_defaults() {
+        let initiate: InitiateParameter = serde_json::from_value(json!({
+            "ServerUniqueIdentifier": "uid",
+            "Name": "[1] Player",
+            "ChannelId": 4,
+            "ChannelPassword": "",
+            "SoundPack": "default",
+            "SwissChannelIds": [],
+        }))
+        .unwrap();
+        assert!(initiate.send_talk_states);
+        assert!(!initiate.send_radio_traffic_states);
+        assert_eq!(initiate.ultra_short_range_distance, 1800.0);
+        assert_eq!(initiate.short_range_distance, 3000.0);
+        assert_eq!(initiate.long_range_distance, 8000.0);
+        assert_eq!(initiate.name_fallback_attempts, 0);
+        assert_eq!(initiate.rusty_chat_version, 0);
+
+        let echo: EchoEffect = serde_json::from_value(json!({})).unwrap();
+        assert_eq!((echo.duration, echo.rolloff, echo.delay), (100, 0.3, 25));
+
+        let muffle: MuffleEffect = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(muffle.intensity, 10);
+
+        let tower: Tower = serde_json::from_value(json!({ "X": 1.0, "Y": 2.0, "Z": 3.0 })).unwrap();
+        assert_eq!(tower.range, 8000.0);
+
+        let self_state: SelfStateUpdateParameter = serde_json::from_value(json!({
+            "Position": { "X": 0.0, "Y": 0.0, "Z": 0.0 },
+            "Rotation": 0.0,
+            "VoiceRange": 3.5,
+            "Echo": null,
+        }))
+        .unwrap();
+        assert!(self_state.is_alive);
+    }
+
+    #[test]
+    fn test_negotiate_version() {
+        assert_eq!(negotiate_version(0), 0);
+        assert_eq!(negotiate_version(1), 1);
+        assert_eq!(negotiate_version(2), 2);
+        assert_eq!(negotiate_version(u32::MAX), RUSTY_CHAT_PROTOCOL_VERSION);
+
+        assert!(!Extension::ErrorMessages.is_supported(0));
+        assert!(Extension::ErrorMessages.is_supported(1));
+        assert!(!Extension::PlayerStateDeltas.is_supported(1));
+        assert!(Extension::PlayerStateDeltas.is_supported(2));
+        assert!(!Extension::MessagePack.is_supported(2));
+        assert!(Extension::MessagePack.is_supported(3));
+    }
+
+    #[test]
+    fn test_parameter_before_command() {
+        let message = parse(
+            r#"{"Parameter":{"Name":"Jane Doe","IsTalking":true},"ServerUniqueIdentifier":"uid","Command":11}"#,
+        );
+
+        assert_eq!(message.command, Command::TalkState);
+        assert!(has_expected_parameter(&message));
+        assert!(serde_json::from_str::<ProtocolMessage>(r#"{"Parameter":null}"#).is_err());
+    }
+
+    #[test]
+    fn test_unknown_command_is_rejected() {
+        let result: Result<ProtocolMessage, _> = serde_json::from_str(
+            r#"{"Command":12,"ServerUniqueIdentifier":null,"Parameter":null}"#,
+        );
+        assert!(result.is_err());
+    }
 }
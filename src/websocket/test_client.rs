@@ -0,0 +1,405 @@
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use ts3plugin::Visibility;
+use tungstenite::client::IntoClientRequest;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::WebSocket;
+use url::Url;
+
+use super::gate::{self, SecurityConfig};
+use super::limits::Limits;
+use super::protocol::Command;
+use super::{websocket_loop, CLIENTS, CLIENTS_BY_INSTANCE};
+use crate::game::GameHandler;
+use crate::teamspeak::mock::MockTeamSpeak;
+
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(2);
+// origin of the fivem nui, the plugin refuses browsers from other origins
+const GAME_ORIGIN: &str = "nui://game";
+
+lazy_static! {
+    // the websocket clients are tracked globally, so only one test server may run at a time
+    static ref TEST_SERVER_LOCK: Mutex<()> = Mutex::new(());
+}
+
+// websocket server on an ephemeral port, backed by a mock teamspeak client
+pub struct TestServer {
+    pub port: u16,
+    pub ts: Arc<MockTeamSpeak>,
+    pub game_ref: Arc<Mutex<GameHandler>>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl TestServer {
+    pub fn start() -> Self {
+        Self::start_with(SecurityConfig::default(), Limits::default())
+    }
+
+    pub fn start_with(config: SecurityConfig, limits: Limits) -> Self {
+        let lock = TEST_SERVER_LOCK
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        CLIENTS.lock().unwrap().clear();
+        CLIENTS_BY_INSTANCE.lock().unwrap().clear();
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind test server");
+        let port = listener.local_addr().unwrap().port();
+        let ts = Arc::new(MockTeamSpeak::new());
+        let game_ref = Arc::new(Mutex::new(GameHandler::new()));
+
+        let config = Arc::new(config);
+        let event_hub = gate::launch(listener, config.clone(), limits, ts.clone());
+
+        let loop_ts = ts.clone();
+        let loop_game_ref = game_ref.clone();
+        std::thread::spawn(move || {
+            let _res = websocket_loop(
+                &event_hub,
+                loop_game_ref,
+                loop_ts.as_ref(),
+                &config,
+                &limits,
+            );
+        });
+
+        Self {
+            port,
+            ts,
+            game_ref,
+            _lock: lock,
+        }
+    }
+
+    pub fn connect(&self) -> FakeGameClient {
+        FakeGameClient::connect(self.port)
+    }
+
+    // `None` when the plugin refused the handshake
+    pub fn connect_with_origin(&self, origin: Option<&str>) -> Option<FakeGameClient> {
+        FakeGameClient::connect_with_origin(self.port, origin)
+    }
+
+    // delivers the moves requested through the mock like the ts3 client would
+    pub fn apply_moves(&self) {
+        for mock_move in self.ts.take_moves() {
+            self.game_ref.lock().unwrap().ts_on_channel_switched(
+                self.ts.as_ref(),
+                mock_move.server_id,
+                mock_move.client_id,
+                mock_move.channel_id,
+                Visibility::Retain,
+            );
+        }
+    }
+}
+
+impl Drop for TestServer {
+    // waits for the server loop to see every client disconnect before the next test starts
+    fn drop(&mut self) {
+        let deadline = Instant::now() + RECEIVE_TIMEOUT;
+        while !CLIENTS.lock().unwrap().is_empty() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+// scripted SaltyChat compatible game client
+pub struct FakeGameClient {
+    socket: WebSocket<MaybeTlsStream<TcpStream>>,
+    pub binary_frames: usize,
+}
+
+impl FakeGameClient {
+    pub fn connect(port: u16) -> Self {
+        Self::connect_with_origin(port, Some(GAME_ORIGIN)).expect("Can't connect")
+    }
+
+    pub fn connect_with_origin(port: u16, origin: Option<&str>) -> Option<Self> {
+        let url = Url::parse(&format!("ws://127.0.0.1:{}", port)).unwrap();
+        let mut request = url.into_client_request().unwrap();
+        if let Some(origin) = origin {
+            request
+                .headers_mut()
+                .insert("Origin", origin.parse().unwrap());
+        }
+        let (socket, _response) = tungstenite::client::connect(request).ok()?;
+
+        if let MaybeTlsStream::Plain(stream) = socket.get_ref() {
+            stream
+                .set_read_timeout(Some(Duration::from_millis(50)))
+                .unwrap();
+        }
+
+        Some(Self {
+            socket,
+            binary_frames: 0,
+        })
+    }
+
+    // true once the plugin closed the connection
+    pub fn is_closed(&mut self) -> bool {
+        let deadline = Instant::now() + RECEIVE_TIMEOUT;
+        while Instant::now() < deadline {
+            match self.socket.read_message() {
+                Ok(tungstenite::Message::Close(_)) => return true,
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(err))
+                    if err.kind() == std::io::ErrorKind::WouldBlock
+                        || err.kind() == std::io::ErrorKind::TimedOut => {}
+                Err(_) => return true,
+            }
+        }
+        false
+    }
+
+    pub fn send_raw(&mut self, text: &str) {
+        self.socket
+            .write_message(tungstenite::Message::Text(text.to_owned()))
+            .unwrap();
+    }
+
+    // sends only the header of a masked text frame, its payload never follows
+    pub fn announce_text_frame(&mut self, payload_len: u64) {
+        let mut header = vec![0x81, 0x80 | 127];
+        header.extend_from_slice(&payload_len.to_be_bytes());
+        header.extend_from_slice(&[0; 4]);
+        if let MaybeTlsStream::Plain(stream) = self.socket.get_mut() {
+            stream.write_all(&header).unwrap();
+        }
+    }
+
+    pub fn send_binary(&mut self, command: Command, server_uid: &str, parameter: Value) {
+        let message = json!({
+            "Command": command as u32,
+            "ServerUniqueIdentifier": server_uid,
+            "Parameter": parameter,
+        });
+        self.socket
+            .write_message(tungstenite::Message::Binary(
+                rmp_serde::to_vec_named(&message).unwrap(),
+            ))
+            .unwrap();
+    }
+
+    pub fn send(&mut self, command: Command, server_uid: &str, parameter: Value) {
+        let message = json!({
+            "Command": command as u32,
+            "ServerUniqueIdentifier": server_uid,
+            "Parameter": parameter,
+        });
+        self.send_raw(&message.to_string());
+    }
+
+    // next message from the plugin, `None` once nothing arrived within the timeout
+    pub fn receive(&mut self, timeout: Duration) -> Option<Value> {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            match self.socket.read_message() {
+                Ok(tungstenite::Message::Text(text)) => {
+                    return Some(serde_json::from_str(&text).expect("plugin sent invalid json"))
+                }
+                Ok(tungstenite::Message::Binary(bin)) => {
+                    self.binary_frames += 1;
+                    return Some(
+                        rmp_serde::from_slice(&bin).expect("plugin sent invalid messagepack"),
+                    );
+                }
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(err))
+                    if err.kind() == std::io::ErrorKind::WouldBlock
+                        || err.kind() == std::io::ErrorKind::TimedOut => {}
+                Err(err) => panic!("websocket error: {}", err),
+            }
+        }
+        None
+    }
+
+    // skips messages until one with the given command arrives
+    pub fn expect(&mut self, command: Command) -> Value {
+        let command = command as u32;
+        let deadline = Instant::now() + RECEIVE_TIMEOUT;
+        while let Some(message) = self.receive(deadline - Instant::now()) {
+            if message["Command"] == command {
+                return message;
+            }
+        }
+        panic!("expected command {} but none arrived", command);
+    }
+
+    // ping round trip that fails if the plugin sent anything else in the meantime
+    pub fn expect_only_pong(&mut self, server_uid: &str) {
+        self.send(Command::Ping, server_uid, Value::Null);
+        let message = self.receive(RECEIVE_TIMEOUT).expect("no pong from plugin");
+        assert_eq!(message["Command"], Command::Pong as u32, "{}", message);
+    }
+
+    // ping round trip, everything sent before has been handled once this returns
+    pub fn sync(&mut self, server_uid: &str) {
+        self.send(Command::Ping, server_uid, Value::Null);
+        self.expect(Command::Pong);
+    }
+
+    // RustyChat handshake, returns the version the plugin agreed on
+    pub fn declare_protocol_version(&mut self, version: u32) -> u64 {
+        self.declare_protocol(version, 0)["Version"]
+            .as_u64()
+            .unwrap()
+    }
+
+    // returns the handshake reply parameter
+    pub fn declare_protocol(&mut self, version: u32, encoding: u8) -> Value {
+        self.send_raw(
+            &json!({
+                "Command": Command::ProtocolVersion as u32,
+                "ServerUniqueIdentifier": null,
+                "Parameter": { "Version": version, "Encoding": encoding },
+            })
+            .to_string(),
+        );
+        self.expect(Command::ProtocolVersion)["Parameter"].clone()
+    }
+
+    pub fn authenticate(&mut self, token: &str) {
+        self.send_raw(
+            &json!({
+                "Command": Command::Authenticate as u32,
+                "ServerUniqueIdentifier": null,
+                "Parameter": { "Token": token },
+            })
+            .to_string(),
+        );
+    }
+
+    pub fn subscribe(&mut self, server_uid: &str, role: u8) {
+        self.send(Command::Subscribe, server_uid, json!({ "Role": role }));
+    }
+
+    pub fn initiate(&mut self, server_uid: &str, name: &str, channel_id: u64, swiss: &[u64]) {
+        self.initiate_with(server_uid, name, channel_id, swiss, json!({}));
+    }
+
+    // `overrides` replaces fields of the default Initiate parameter
+    pub fn initiate_with(
+        &mut self,
+        server_uid: &str,
+        name: &str,
+        channel_id: u64,
+        swiss: &[u64],
+        overrides: Value,
+    ) {
+        let mut parameter = json!({
+                "ServerUniqueIdentifier": server_uid,
+                "Name": name,
+                "ChannelId": channel_id,
+                "ChannelPassword": "",
+                "SoundPack": "default",
+                "SwissChannelIds": swiss,
+                "SendTalkStates": true,
+                "SendRadioTrafficStates": true,
+                "UltraShortRangeDistance": 1800.0,
+                "ShortRangeDistance": 3000.0,
+                "LongRangeDistance": 8000.0,
+        });
+        if let (Some(parameter), Value::Object(overrides)) = (parameter.as_object_mut(), overrides)
+        {
+            parameter.extend(overrides);
+        }
+
+        self.send(Command::Initiate, server_uid, parameter);
+    }
+
+    pub fn bulk_update(&mut self, server_uid: &str, players: &[(&str, [f32; 3])], own: [f32; 3]) {
+        let player_states: Vec<Value> = players
+            .iter()
+            .map(|(name, position)| {
+                json!({
+                    "Name": name,
+                    "Position": { "X": position[0], "Y": position[1], "Z": position[2] },
+                    "Rotation": 0.0,
+                    "VoiceRange": 8.0,
+                    "IsAlive": true,
+                    "VolumeOverride": null,
+                    "DistanceCulled": false,
+                    "Muffle": null,
+                })
+            })
+            .collect();
+
+        self.send(
+            Command::BulkUpdate,
+            server_uid,
+            json!({
+                "PlayerStates": player_states,
+                "SelfState": {
+                    "Position": { "X": own[0], "Y": own[1], "Z": own[2] },
+                    "Rotation": 0.0,
+                    "VoiceRange": 8.0,
+                    "IsAlive": true,
+                    "Echo": null,
+                },
+            }),
+        );
+    }
+
+    pub fn radio_communication_update(&mut self, server_uid: &str, name: &str, secondary: bool) {
+        self.send(
+            Command::RadioCommunicationUpdate,
+            server_uid,
+            json!({
+                "Name": name,
+                "SenderRadioType": 2,
+                "OwnRadioType": 2,
+                "PlayMicClick": true,
+                "Volume": null,
+                "Direct": false,
+                "Secondary": secondary,
+                "RelayedBy": [],
+            }),
+        );
+    }
+
+    pub fn stop_radio_communication(&mut self, server_uid: &str, name: &str) {
+        self.send(
+            Command::StopRadioCommunication,
+            server_uid,
+            json!({ "Name": name, "PlayMicClick": true }),
+        );
+    }
+
+    pub fn phone_communication_update(&mut self, server_uid: &str, name: &str, signal: i32) {
+        self.send(
+            Command::PhoneCommunicationUpdate,
+            server_uid,
+            json!({
+                "Name": name,
+                "SignalStrength": signal,
+                "Volume": null,
+                "Direct": false,
+                "RelayedBy": [],
+            }),
+        );
+    }
+
+    pub fn stop_phone_communication(&mut self, server_uid: &str, name: &str) {
+        self.send(
+            Command::StopPhoneCommunication,
+            server_uid,
+            json!({ "Name": name }),
+        );
+    }
+
+    pub fn reset(&mut self, server_uid: &str) {
+        self.send(Command::Reset, server_uid, Value::Null);
+    }
+}
+
+impl Drop for FakeGameClient {
+    fn drop(&mut self) {
+        let _ = self.socket.close(None);
+        let _ = self.socket.write_pending();
+    }
+}